@@ -0,0 +1,108 @@
+//! Command line driver for building and checking waveling programs.
+//!
+//! Programs are read from Lua scripts, via [waveling_lua].
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+
+use waveling_core::*;
+
+#[derive(Parser)]
+#[command(about = "Build and check waveling programs")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the checking passes over a program and print any diagnostics.
+    Check { script: PathBuf },
+
+    /// Print the program's graph in graphviz format.
+    Dot {
+        script: PathBuf,
+
+        /// Print the graph before any passes have run, without the implicit start and final edges.
+        #[arg(long)]
+        raw: bool,
+    },
+}
+
+fn load(script: &Path) -> Result<Program> {
+    let source = std::fs::read_to_string(script)
+        .with_context(|| format!("Unable to read {}", script.display()))?;
+    waveling_lua::program_from_lua(&source, &script.display().to_string())
+}
+
+/// Run the checking passes, stopping at the first that fails.
+fn check(program: &mut Program, diagnostics: &mut DiagnosticCollection) {
+    if insert_start_final_edges(program, diagnostics).is_err() {
+        return;
+    }
+
+    if validate_feedback(program, diagnostics).is_err() {
+        return;
+    }
+
+    let _ = type_inference(program, diagnostics);
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    match cli.command {
+        Command::Check { script } => {
+            let mut program = load(&script)?;
+            let mut diagnostics = DiagnosticCollection::new();
+            check(&mut program, &mut diagnostics);
+            print!("{}", diagnostics);
+            Ok(!diagnostics.has_errors())
+        }
+        Command::Dot { script, raw } => {
+            let mut program = load(&script)?;
+            if !raw {
+                let mut diagnostics = DiagnosticCollection::new();
+                if insert_start_final_edges(&mut program, &mut diagnostics).is_err() {
+                    eprint!("{}", diagnostics);
+                    return Ok(false);
+                }
+            }
+            println!("{}", program.graphviz());
+            Ok(true)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_type_errors() {
+        let mut program = waveling_lua::program_from_lua(
+            r#"
+local x = waveling.input("f32", 1)
+local out = waveling.output("f64", 1)
+waveling.connect(x, out, 0)
+"#,
+            "mismatch.lua",
+        )
+        .unwrap();
+
+        let mut diagnostics = DiagnosticCollection::new();
+        check(&mut program, &mut diagnostics);
+        assert!(diagnostics.has_errors());
+    }
+}
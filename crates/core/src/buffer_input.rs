@@ -0,0 +1,15 @@
+use crate::VectorDescriptor;
+
+/// A buffer input is a block of frames which the host provides all at once, for example a wavetable or a segment of
+/// an impulse response.
+///
+/// Unlike ordinary inputs, which present one frame per sample, buffers are read at an index with
+/// [crate::Op::ReadInputIndexed].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BufferInput {
+    /// The kind of data in each frame.
+    pub vector: VectorDescriptor,
+
+    /// The number of frames in the buffer.
+    pub length: u64,
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use crate::{PrimitiveType, VectorDescriptor};
+use crate::{CompareOp, PrimitiveType, Rate, RoundingMode, VectorDescriptor};
 
 /// A vector constant.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -27,6 +27,9 @@ pub enum ConstantFoldingError {
 
     #[error("Constant widths are not the same, and neither can be broadcast")]
     IncompatibleWidths,
+
+    #[error("Stereo operations need a stereo pair of width 2, and scalar parameters")]
+    NotStereo,
 }
 
 impl Constant {
@@ -50,22 +53,99 @@ impl Constant {
         w as u64
     }
 
+    /// The type of this constant.  Constants never change, so they are control rate.
     pub fn vector_descriptor(&self) -> VectorDescriptor {
-        VectorDescriptor {
-            primitive: self.primitive_type(),
-            width: self.width(),
+        VectorDescriptor::new(self.primitive_type(), self.width()).with_rate(Rate::Control)
+    }
+}
+
+/// How to print the floats in a constant.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum FloatFormat {
+    /// The shortest decimal representation which parses back to exactly the same value.
+    ///
+    /// Always includes a `.` or an exponent, so that floats can't be mistaken for integers.
+    #[default]
+    Shortest,
+
+    /// C99-style hexadecimal floats, e.g. `0x1.8p+1`, which are exact by construction.
+    Hex,
+}
+
+/// Display a constant with a given [FloatFormat].  Built by [Constant::display_with].
+pub struct ConstantDisplay<'a> {
+    constant: &'a Constant,
+    format: FloatFormat,
+}
+
+/// Format a float as a C99 hex float.
+///
+/// f32 is widened to f64 first, which is exact.
+fn hex_float(x: f64) -> String {
+    if x.is_nan() {
+        return "nan".to_string();
+    }
+
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if x.is_infinite() {
+        return format!("{}inf", sign);
+    }
+
+    let bits = x.to_bits();
+    let biased_exp = ((bits >> 52) & 0x7ff) as i64;
+    let mantissa = bits & ((1u64 << 52) - 1);
+
+    let (lead, exp) = match (biased_exp, mantissa) {
+        (0, 0) => return format!("{}0x0p+0", sign),
+        // Subnormal.
+        (0, _) => (0, -1022),
+        _ => (1, biased_exp - 1023),
+    };
+
+    let digits = format!("{:013x}", mantissa);
+    let digits = digits.trim_end_matches('0');
+    if digits.is_empty() {
+        format!("{}0x{}p{:+}", sign, lead, exp)
+    } else {
+        format!("{}0x{}.{}p{:+}", sign, lead, digits, exp)
+    }
+}
+
+impl Constant {
+    /// Display this constant with the given float format.
+    ///
+    /// The [Display] impl uses [FloatFormat::Shortest].
+    pub fn display_with(&self, format: FloatFormat) -> ConstantDisplay<'_> {
+        ConstantDisplay {
+            constant: self,
+            format,
         }
     }
 }
 
-impl Display for Constant {
+impl Display for ConstantDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use itertools::Itertools;
 
-        let (ty, inner) = match self {
+        // Rust's Debug impl for floats is the shortest round-trip representation, and unlike Display always has a `.`
+        // or an exponent.
+        let float = |x: f64, shortest: String| match self.format {
+            FloatFormat::Shortest => shortest,
+            FloatFormat::Hex => hex_float(x),
+        };
+
+        let (ty, inner) = match self.constant {
             Constant::Bool(x) => ("bool", x.iter().join(", ")),
-            Constant::F32(x) => ("f32", x.iter().join(", ")),
-            Constant::F64(x) => ("f64", x.iter().join(", ")),
+            Constant::F32(x) => (
+                "f32",
+                x.iter()
+                    .map(|v| float(*v as f64, format!("{:?}", v)))
+                    .join(", "),
+            ),
+            Constant::F64(x) => (
+                "f64",
+                x.iter().map(|v| float(*v, format!("{:?}", v))).join(", "),
+            ),
             Constant::I64(x) => ("i64", x.iter().join(", ")),
         };
 
@@ -73,6 +153,12 @@ impl Display for Constant {
     }
 }
 
+impl Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_with(FloatFormat::Shortest).fmt(f)
+    }
+}
+
 fn do_binop(
     left: &Constant,
     right: &Constant,
@@ -96,7 +182,7 @@ fn do_binop(
             Ok(Constant::$output_variant(
                 (0..total_len)
                     .into_iter()
-                    .map(|i| case_fn($l[(i % total_len) as usize], $r[(i % total_len) as usize]))
+                    .map(|i| case_fn($l[i as usize % $l.len()], $r[i as usize % $r.len()]))
                     .collect(),
             ))
         }};
@@ -113,9 +199,46 @@ fn do_binop(
     }
 }
 
+/// Integer division, following the rules in the documentation on the math impl block of [Constant].
+fn safe_int_div(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        0
+    } else {
+        a.wrapping_div(b)
+    }
+}
+
+/// Integer remainder, following the rules in the documentation on the math impl block of [Constant].
+fn safe_int_rem(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        0
+    } else {
+        a.wrapping_rem(b)
+    }
+}
+
+/// Get the broadcast width of some constants, or an error if they can't be broadcast together.
+fn broadcast_width(constants: &[&Constant]) -> Result<u64, ConstantFoldingError> {
+    if constants.iter().any(|c| c.width() == 0) {
+        return Err(ConstantFoldingError::ZeroWidthConstant);
+    }
+
+    let total_len = constants.iter().map(|c| c.width()).max().unwrap_or(0);
+    if constants
+        .iter()
+        .any(|c| c.width() != 1 && c.width() != total_len)
+    {
+        return Err(ConstantFoldingError::IncompatibleWidths);
+    }
+
+    Ok(total_len)
+}
+
 /// Punch out operations which work on i64/f32/f64.
+///
+/// Floats use the operator from `std::ops`, but the integer case is passed in so that it can be defined everywhere.
 macro_rules! numeric_binop {
-    ($op_name: ident, $trait: ident) => {
+    ($op_name: ident, $trait: ident, $int_case: expr) => {
         paste::paste! {
             pub fn [<fold_ $op_name>](&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
                 use std::ops::$trait;
@@ -124,7 +247,7 @@ macro_rules! numeric_binop {
                     self,
                     other,
                     None,
-                    Some(&mut |a: i64, b: i64| a.$op_name(b)),
+                    Some(&mut $int_case),
                     Some(&mut |a: f32, b: f32| a.$op_name(b)),
                     Some(&mut |a: f64, b: f64| a.$op_name(b))
                 )
@@ -136,12 +259,22 @@ macro_rules! numeric_binop {
 /// # Mathematical operations between constants.
 ///
 /// These are used for constant folding, and also for the interpreters.
+///
+/// All operations have a defined result for every input, and backends must match them:
+///
+/// - Integer arithmetic wraps on overflow, as two's complement.  This includes negating `i64::MIN` and dividing it by
+///   -1, both of which give `i64::MIN`.
+/// - Integer division and remainder by zero give 0.
+/// - Shifts use only the low 6 bits of the shift amount, so shifting by 64 is shifting by 0 and shifting by -1 is
+///   shifting by 63.  Right shifts are arithmetic.
+/// - Floats follow IEEE 754, so division by zero gives an infinity or NaN, and NaN propagates.
+/// - Conversions from floats to integers saturate at the bounds of i64, and NaN converts to 0.
 impl Constant {
-    numeric_binop!(add, Add);
-    numeric_binop!(sub, Sub);
-    numeric_binop!(mul, Mul);
-    numeric_binop!(div, Div);
-    numeric_binop!(rem, Rem);
+    numeric_binop!(add, Add, |a: i64, b: i64| a.wrapping_add(b));
+    numeric_binop!(sub, Sub, |a: i64, b: i64| a.wrapping_sub(b));
+    numeric_binop!(mul, Mul, |a: i64, b: i64| a.wrapping_mul(b));
+    numeric_binop!(div, Div, safe_int_div);
+    numeric_binop!(rem, Rem, safe_int_rem);
 
     /// Negate this constant.
     pub fn fold_neg(&self) -> Result<Constant, ConstantFoldingError> {
@@ -150,9 +283,819 @@ impl Constant {
             self,
             self,
             None,
+            Some(&mut |a: i64, _b| a.wrapping_neg()),
             Some(&mut |a, _b| -a),
             Some(&mut |a, _b| -a),
-            Some(&mut |a, _b| -a),
         )
     }
+
+    /// Take the absolute value of this constant.  `i64::MIN` wraps to itself.
+    pub fn fold_abs(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            Some(&mut |a: i64, _b| a.wrapping_abs()),
+            Some(&mut |a: f32, _b| a.abs()),
+            Some(&mut |a: f64, _b| a.abs()),
+        )
+    }
+
+    pub fn fold_floor(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.floor()),
+            Some(&mut |a: f64, _b| a.floor()),
+        )
+    }
+
+    pub fn fold_ceil(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.ceil()),
+            Some(&mut |a: f64, _b| a.ceil()),
+        )
+    }
+
+    /// Round to the nearest integer, with halfway cases going away from zero.
+    pub fn fold_round(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.round()),
+            Some(&mut |a: f64, _b| a.round()),
+        )
+    }
+
+    pub fn fold_trunc(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.trunc()),
+            Some(&mut |a: f64, _b| a.trunc()),
+        )
+    }
+
+    /// The fractional part, as `x - floor(x)`, so that it is never negative.
+    pub fn fold_fract(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a - a.floor()),
+            Some(&mut |a: f64, _b| a - a.floor()),
+        )
+    }
+
+    pub fn fold_sqrt(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.sqrt()),
+            Some(&mut |a: f64, _b| a.sqrt()),
+        )
+    }
+
+    pub fn fold_exp(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.exp()),
+            Some(&mut |a: f64, _b| a.exp()),
+        )
+    }
+
+    /// The natural log of this constant.
+    pub fn fold_log(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            self,
+            None,
+            None,
+            Some(&mut |a: f32, _b| a.ln()),
+            Some(&mut |a: f64, _b| a.ln()),
+        )
+    }
+
+    /// Cast this constant to another primitive.
+    ///
+    /// Bools convert to 0 or 1, and anything converts to a bool by comparing it with 0, so NaN is true.  Floats
+    /// convert to integers by truncating.  Other conversions round to nearest.
+    pub fn fold_cast(&self, to: PrimitiveType) -> Result<Constant, ConstantFoldingError> {
+        if self.width() == 0 {
+            return Err(ConstantFoldingError::ZeroWidthConstant);
+        }
+
+        macro_rules! arm {
+            ($v: ident, $variant: ident, $f: expr) => {
+                Constant::$variant($v.iter().copied().map($f).collect())
+            };
+        }
+
+        use Constant::*;
+
+        Ok(match (self, to) {
+            (Bool(v), PrimitiveType::Bool) => Bool(v.clone()),
+            (Bool(v), PrimitiveType::I64) => arm!(v, I64, |x| x as i64),
+            (Bool(v), PrimitiveType::F32) => arm!(v, F32, |x| if x { 1.0 } else { 0.0 }),
+            (Bool(v), PrimitiveType::F64) => arm!(v, F64, |x| if x { 1.0 } else { 0.0 }),
+            (I64(v), PrimitiveType::Bool) => arm!(v, Bool, |x| x != 0),
+            (I64(v), PrimitiveType::I64) => I64(v.clone()),
+            (I64(v), PrimitiveType::F32) => arm!(v, F32, |x| x as f32),
+            (I64(v), PrimitiveType::F64) => arm!(v, F64, |x| x as f64),
+            (F32(v), PrimitiveType::Bool) => arm!(v, Bool, |x| x != 0.0),
+            (F32(v), PrimitiveType::I64) => arm!(v, I64, |x| x as i64),
+            (F32(v), PrimitiveType::F32) => F32(v.clone()),
+            (F32(v), PrimitiveType::F64) => arm!(v, F64, |x| x as f64),
+            (F64(v), PrimitiveType::Bool) => arm!(v, Bool, |x| x != 0.0),
+            (F64(v), PrimitiveType::I64) => arm!(v, I64, |x| x as i64),
+            (F64(v), PrimitiveType::F32) => arm!(v, F32, |x| x as f32),
+            (F64(v), PrimitiveType::F64) => F64(v.clone()),
+        })
+    }
+
+    /// Convert a float constant to i64 with the given rounding mode.
+    pub fn fold_float_to_int(&self, mode: RoundingMode) -> Result<Constant, ConstantFoldingError> {
+        fn round(x: f64, mode: RoundingMode) -> i64 {
+            // `as` saturates and sends NaN to 0, which is the behavior we document.
+            match mode {
+                RoundingMode::Trunc => x.trunc() as i64,
+                RoundingMode::Floor => x.floor() as i64,
+                RoundingMode::NearestEven => x.round_ties_even() as i64,
+            }
+        }
+
+        if self.width() == 0 {
+            return Err(ConstantFoldingError::ZeroWidthConstant);
+        }
+
+        match self {
+            // Widening f32 to f64 is exact, so rounding the f64 gives the same answer.
+            Constant::F32(v) => Ok(Constant::I64(
+                v.iter().map(|x| round(*x as f64, mode)).collect(),
+            )),
+            Constant::F64(v) => Ok(Constant::I64(v.iter().map(|x| round(*x, mode)).collect())),
+            _ => Err(ConstantFoldingError::UnsupportedType),
+        }
+    }
+
+    pub fn fold_logical_and(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, other, Some(&mut |a, b| a && b), None, None, None)
+    }
+
+    pub fn fold_logical_or(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, other, Some(&mut |a, b| a || b), None, None, None)
+    }
+
+    pub fn fold_logical_not(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, self, Some(&mut |a, _b| !a), None, None, None)
+    }
+
+    /// Compare two constants, producing a bool constant.
+    pub fn fold_compare(
+        &self,
+        other: &Constant,
+        op: CompareOp,
+    ) -> Result<Constant, ConstantFoldingError> {
+        let total_len = broadcast_width(&[self, other])? as usize;
+
+        macro_rules! arm {
+            ($l: ident, $r: ident) => {
+                Ok(Constant::Bool(
+                    (0..total_len)
+                        .map(|i| op.compare($l[i % $l.len()], $r[i % $r.len()]))
+                        .collect(),
+                ))
+            };
+        }
+
+        use Constant::*;
+
+        match (self, other) {
+            (Bool(_), Bool(_)) if !op.denied_primitives().is_empty() => {
+                Err(ConstantFoldingError::UnsupportedType)
+            }
+            (Bool(l), Bool(r)) => arm!(l, r),
+            (I64(l), I64(r)) => arm!(l, r),
+            (F32(l), F32(r)) => arm!(l, r),
+            (F64(l), F64(r)) => arm!(l, r),
+            (_, _) => Err(ConstantFoldingError::IncompatibleTypes),
+        }
+    }
+
+    /// Select between two constants, with this constant as the condition.
+    pub fn fold_select(
+        &self,
+        on_true: &Constant,
+        on_false: &Constant,
+    ) -> Result<Constant, ConstantFoldingError> {
+        let Constant::Bool(cond) = self else {
+            return Err(ConstantFoldingError::UnsupportedType);
+        };
+        let total_len = broadcast_width(&[self, on_true, on_false])? as usize;
+
+        macro_rules! arm {
+            ($variant: ident, $t: ident, $f: ident) => {
+                Ok(Constant::$variant(
+                    (0..total_len)
+                        .map(|i| {
+                            if cond[i % cond.len()] {
+                                $t[i % $t.len()]
+                            } else {
+                                $f[i % $f.len()]
+                            }
+                        })
+                        .collect(),
+                ))
+            };
+        }
+
+        use Constant::*;
+
+        match (on_true, on_false) {
+            (Bool(t), Bool(f)) => arm!(Bool, t, f),
+            (I64(t), I64(f)) => arm!(I64, t, f),
+            (F32(t), F32(f)) => arm!(F32, t, f),
+            (F64(t), F64(f)) => arm!(F64, t, f),
+            (_, _) => Err(ConstantFoldingError::IncompatibleTypes),
+        }
+    }
+
+    /// Interpolate from this constant to `other` by `t`, as `self + (other - self) * t`.
+    pub fn fold_lerp(
+        &self,
+        other: &Constant,
+        t: &Constant,
+    ) -> Result<Constant, ConstantFoldingError> {
+        let total_len = broadcast_width(&[self, other, t])? as usize;
+
+        macro_rules! arm {
+            ($variant: ident, $a: ident, $b: ident, $t: ident) => {
+                Ok(Constant::$variant(
+                    (0..total_len)
+                        .map(|i| {
+                            let a = $a[i % $a.len()];
+                            a + ($b[i % $b.len()] - a) * $t[i % $t.len()]
+                        })
+                        .collect(),
+                ))
+            };
+        }
+
+        use Constant::*;
+
+        match (self, other, t) {
+            (F32(a), F32(b), F32(t)) => arm!(F32, a, b, t),
+            (F64(a), F64(b), F64(t)) => arm!(F64, a, b, t),
+            (Bool(_), Bool(_), Bool(_)) | (I64(_), I64(_), I64(_)) => {
+                Err(ConstantFoldingError::UnsupportedType)
+            }
+            _ => Err(ConstantFoldingError::IncompatibleTypes),
+        }
+    }
+
+    /// Encode a stereo pair of left and right into mid and side, as `((l + r) / sqrt(2), (l - r) / sqrt(2))`.
+    pub fn fold_ms_encode(&self) -> Result<Constant, ConstantFoldingError> {
+        if self.width() != 2 {
+            return Err(ConstantFoldingError::NotStereo);
+        }
+
+        macro_rules! arm {
+            ($variant: ident, $prim: ident, $v: ident) => {{
+                let k = std::$prim::consts::FRAC_1_SQRT_2;
+                Ok(Constant::$variant(vec![
+                    ($v[0] + $v[1]) * k,
+                    ($v[0] - $v[1]) * k,
+                ]))
+            }};
+        }
+
+        match self {
+            Constant::F32(v) => arm!(F32, f32, v),
+            Constant::F64(v) => arm!(F64, f64, v),
+            Constant::Bool(_) | Constant::I64(_) => Err(ConstantFoldingError::UnsupportedType),
+        }
+    }
+
+    /// Decode mid and side into a stereo pair of left and right, undoing [Constant::fold_ms_encode].
+    ///
+    /// The transform is its own inverse, so this is the same math.
+    pub fn fold_ms_decode(&self) -> Result<Constant, ConstantFoldingError> {
+        self.fold_ms_encode()
+    }
+
+    /// Change the width of a stereo pair.
+    ///
+    /// The width `w` is clamped to `[0, 2]`.  Mid is scaled by `sqrt(2) * cos(w * pi / 4)` and side by `sqrt(2) *
+    /// sin(w * pi / 4)`, so at 1 both gains are 1, and the squares of the gains always sum to 2.
+    pub fn fold_stereo_width(&self, width: &Constant) -> Result<Constant, ConstantFoldingError> {
+        if width.width() != 1 {
+            return Err(ConstantFoldingError::NotStereo);
+        }
+
+        let mid_side = self.fold_ms_encode()?;
+
+        macro_rules! arm {
+            ($variant: ident, $prim: ident, $ms: ident, $w: ident) => {{
+                let theta = $w[0].clamp(0.0, 2.0) * std::$prim::consts::FRAC_PI_4;
+                let k = std::$prim::consts::SQRT_2;
+                Constant::$variant(vec![$ms[0] * k * theta.cos(), $ms[1] * k * theta.sin()])
+                    .fold_ms_decode()
+            }};
+        }
+
+        match (&mid_side, width) {
+            (Constant::F32(ms), Constant::F32(w)) => arm!(F32, f32, ms, w),
+            (Constant::F64(ms), Constant::F64(w)) => arm!(F64, f64, ms, w),
+            _ => Err(ConstantFoldingError::IncompatibleTypes),
+        }
+    }
+
+    pub fn fold_bit_and(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, other, None, Some(&mut |a, b| a & b), None, None)
+    }
+
+    pub fn fold_bit_or(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, other, None, Some(&mut |a, b| a | b), None, None)
+    }
+
+    pub fn fold_bit_xor(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, other, None, Some(&mut |a, b| a ^ b), None, None)
+    }
+
+    pub fn fold_shl(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            other,
+            None,
+            Some(&mut |a: i64, b| a.wrapping_shl(b as u32)),
+            None,
+            None,
+        )
+    }
+
+    pub fn fold_shr(&self, other: &Constant) -> Result<Constant, ConstantFoldingError> {
+        do_binop(
+            self,
+            other,
+            None,
+            Some(&mut |a: i64, b| a.wrapping_shr(b as u32)),
+            None,
+            None,
+        )
+    }
+
+    pub fn fold_bit_not(&self) -> Result<Constant, ConstantFoldingError> {
+        do_binop(self, self, None, Some(&mut |a, _b| !a), None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_division_by_zero() {
+        let l = Constant::I64(vec![5, -5, 0]);
+        let zero = Constant::I64(vec![0]);
+        assert_eq!(l.fold_div(&zero).unwrap(), Constant::I64(vec![0, 0, 0]));
+        assert_eq!(l.fold_rem(&zero).unwrap(), Constant::I64(vec![0, 0, 0]));
+    }
+
+    #[test]
+    fn test_integer_overflow_wraps() {
+        let min = Constant::I64(vec![i64::MIN]);
+        let max = Constant::I64(vec![i64::MAX]);
+        let one = Constant::I64(vec![1]);
+        let neg_one = Constant::I64(vec![-1]);
+
+        assert_eq!(max.fold_add(&one).unwrap(), min);
+        assert_eq!(min.fold_sub(&one).unwrap(), max);
+        assert_eq!(min.fold_neg().unwrap(), min);
+        assert_eq!(min.fold_div(&neg_one).unwrap(), min);
+        assert_eq!(min.fold_rem(&neg_one).unwrap(), Constant::I64(vec![0]));
+    }
+
+    #[test]
+    fn test_float_division_is_ieee() {
+        let res = Constant::F32(vec![1.0, -1.0, 0.0])
+            .fold_div(&Constant::F32(vec![0.0]))
+            .unwrap();
+        let Constant::F32(v) = res else {
+            panic!("Expected f32");
+        };
+        assert_eq!(v[0], f32::INFINITY);
+        assert_eq!(v[1], f32::NEG_INFINITY);
+        assert!(v[2].is_nan());
+    }
+
+    #[test]
+    fn test_logical_ops() {
+        let l = Constant::Bool(vec![false, false, true, true]);
+        let r = Constant::Bool(vec![false, true, false, true]);
+        assert_eq!(
+            l.fold_logical_and(&r).unwrap(),
+            Constant::Bool(vec![false, false, false, true])
+        );
+        assert_eq!(
+            l.fold_logical_or(&r).unwrap(),
+            Constant::Bool(vec![false, true, true, true])
+        );
+        assert_eq!(
+            l.fold_logical_not().unwrap(),
+            Constant::Bool(vec![true, true, false, false])
+        );
+
+        assert!(matches!(
+            Constant::I64(vec![1]).fold_logical_and(&Constant::I64(vec![1])),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+        assert!(matches!(
+            l.fold_add(&r),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_bit_ops() {
+        let l = Constant::I64(vec![0b1100]);
+        let r = Constant::I64(vec![0b1010]);
+        assert_eq!(l.fold_bit_and(&r).unwrap(), Constant::I64(vec![0b1000]));
+        assert_eq!(l.fold_bit_or(&r).unwrap(), Constant::I64(vec![0b1110]));
+        assert_eq!(l.fold_bit_xor(&r).unwrap(), Constant::I64(vec![0b0110]));
+        assert_eq!(l.fold_bit_not().unwrap(), Constant::I64(vec![!0b1100]));
+
+        assert!(matches!(
+            Constant::F32(vec![1.0]).fold_bit_and(&Constant::F32(vec![1.0])),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_shifts() {
+        let one = Constant::I64(vec![1]);
+        let shifts = Constant::I64(vec![0, 3, 63, 64, -1]);
+        assert_eq!(
+            one.fold_shl(&shifts).unwrap(),
+            Constant::I64(vec![1, 8, i64::MIN, 1, i64::MIN])
+        );
+
+        // Right shifts keep the sign.
+        assert_eq!(
+            Constant::I64(vec![-8, 8])
+                .fold_shr(&Constant::I64(vec![2]))
+                .unwrap(),
+            Constant::I64(vec![-2, 2])
+        );
+    }
+
+    #[test]
+    fn test_shortest_float_format_round_trips() {
+        let f32s = vec![
+            0.1f32,
+            1.0,
+            -0.0,
+            1e-7,
+            f32::MAX,
+            f32::MIN_POSITIVE,
+            f32::MIN_POSITIVE / 8.0,
+        ];
+        let f64s = vec![0.1f64, 1.0 / 3.0, 1e300, f64::MIN_POSITIVE / 1024.0];
+
+        let printed = Constant::F32(f32s.clone()).to_string();
+        let inner = printed
+            .strip_prefix("f32[")
+            .unwrap()
+            .strip_suffix(']')
+            .unwrap();
+        let parsed = inner
+            .split(", ")
+            .map(|x| x.parse::<f32>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed.iter().map(|x| x.to_bits()).collect::<Vec<_>>(),
+            f32s.iter().map(|x| x.to_bits()).collect::<Vec<_>>()
+        );
+
+        let printed = Constant::F64(f64s.clone()).to_string();
+        let inner = printed
+            .strip_prefix("f64[")
+            .unwrap()
+            .strip_suffix(']')
+            .unwrap();
+        let parsed = inner
+            .split(", ")
+            .map(|x| x.parse::<f64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, f64s);
+
+        // Whole numbers must not look like integers.
+        assert_eq!(Constant::F32(vec![1.0, 2.0]).to_string(), "f32[1.0, 2.0]");
+    }
+
+    #[test]
+    fn test_hex_float_format() {
+        let c = Constant::F64(vec![1.0, 3.0, 0.1, -0.0, 5e-324, f64::INFINITY, f64::NAN]);
+        assert_eq!(
+            c.display_with(FloatFormat::Hex).to_string(),
+            "f64[0x1p+0, 0x1.8p+1, 0x1.999999999999ap-4, -0x0p+0, 0x0.0000000000001p-1022, inf, nan]"
+        );
+
+        let c = Constant::F32(vec![0.5]);
+        assert_eq!(c.display_with(FloatFormat::Hex).to_string(), "f32[0x1p-1]");
+    }
+
+    #[test]
+    fn test_unary_math() {
+        assert_eq!(
+            Constant::F64(vec![4.0, 0.0]).fold_sqrt().unwrap(),
+            Constant::F64(vec![2.0, 0.0])
+        );
+        assert_eq!(
+            Constant::F32(vec![0.0]).fold_exp().unwrap(),
+            Constant::F32(vec![1.0])
+        );
+        assert_eq!(
+            Constant::F64(vec![1.0, 0.0]).fold_log().unwrap(),
+            Constant::F64(vec![0.0, f64::NEG_INFINITY])
+        );
+        assert_eq!(
+            Constant::I64(vec![-3, 3, i64::MIN]).fold_abs().unwrap(),
+            Constant::I64(vec![3, 3, i64::MIN])
+        );
+
+        // Negative square roots are NaN, per IEEE.
+        match Constant::F32(vec![-1.0]).fold_sqrt().unwrap() {
+            Constant::F32(v) => assert!(v[0].is_nan()),
+            _ => panic!("Expected f32"),
+        }
+
+        assert!(matches!(
+            Constant::I64(vec![4]).fold_sqrt(),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_rounding() {
+        let c = Constant::F64(vec![-1.5, -0.25, 0.5, 2.75]);
+        assert_eq!(
+            c.fold_floor().unwrap(),
+            Constant::F64(vec![-2.0, -1.0, 0.0, 2.0])
+        );
+        assert_eq!(
+            c.fold_ceil().unwrap(),
+            Constant::F64(vec![-1.0, -0.0, 1.0, 3.0])
+        );
+        assert_eq!(
+            c.fold_round().unwrap(),
+            Constant::F64(vec![-2.0, -0.0, 1.0, 3.0])
+        );
+        assert_eq!(
+            c.fold_trunc().unwrap(),
+            Constant::F64(vec![-1.0, -0.0, 0.0, 2.0])
+        );
+        assert_eq!(
+            c.fold_fract().unwrap(),
+            Constant::F64(vec![0.5, 0.75, 0.5, 0.75])
+        );
+
+        assert!(matches!(
+            Constant::I64(vec![1]).fold_floor(),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_casts() {
+        let c = Constant::F64(vec![-1.5, 0.0, 2.7, f64::NAN, 1e300]);
+        assert_eq!(
+            c.fold_cast(PrimitiveType::I64).unwrap(),
+            Constant::I64(vec![-1, 0, 2, 0, i64::MAX])
+        );
+        assert_eq!(
+            c.fold_cast(PrimitiveType::Bool).unwrap(),
+            Constant::Bool(vec![true, false, true, true, true])
+        );
+        assert_eq!(
+            Constant::Bool(vec![true, false])
+                .fold_cast(PrimitiveType::F32)
+                .unwrap(),
+            Constant::F32(vec![1.0, 0.0])
+        );
+        assert_eq!(
+            Constant::I64(vec![-3])
+                .fold_cast(PrimitiveType::F64)
+                .unwrap(),
+            Constant::F64(vec![-3.0])
+        );
+    }
+
+    #[test]
+    fn test_float_to_int() {
+        let c = Constant::F32(vec![-1.5, -0.5, 0.5, 1.5, 2.5]);
+        assert_eq!(
+            c.fold_float_to_int(RoundingMode::Trunc).unwrap(),
+            Constant::I64(vec![-1, 0, 0, 1, 2])
+        );
+        assert_eq!(
+            c.fold_float_to_int(RoundingMode::Floor).unwrap(),
+            Constant::I64(vec![-2, -1, 0, 1, 2])
+        );
+        assert_eq!(
+            c.fold_float_to_int(RoundingMode::NearestEven).unwrap(),
+            Constant::I64(vec![-2, 0, 0, 2, 2])
+        );
+
+        assert_eq!(
+            Constant::F64(vec![f64::NAN, f64::NEG_INFINITY])
+                .fold_float_to_int(RoundingMode::Floor)
+                .unwrap(),
+            Constant::I64(vec![0, i64::MIN])
+        );
+        assert!(matches!(
+            Constant::I64(vec![1]).fold_float_to_int(RoundingMode::Trunc),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    fn stereo(c: &Constant) -> (f64, f64) {
+        match c {
+            Constant::F64(v) => (v[0], v[1]),
+            _ => panic!("Expected an f64 stereo pair, got {:?}", c),
+        }
+    }
+
+    fn energy(c: &Constant) -> f64 {
+        let (l, r) = stereo(c);
+        l * l + r * r
+    }
+
+    #[test]
+    fn test_mid_side() {
+        // Mono in means no side, and a mid which sums the channels.
+        let mono = Constant::F64(vec![0.5, 0.5]);
+        let (mid, side) = stereo(&mono.fold_ms_encode().unwrap());
+        assert!((mid - 0.5 * std::f64::consts::SQRT_2).abs() < 1e-12);
+        assert_eq!(side, 0.0);
+
+        for pair in [[0.5, 0.5], [1.0, 0.0], [0.3, -0.7], [-0.25, 0.9]] {
+            let lr = Constant::F64(pair.to_vec());
+            let ms = lr.fold_ms_encode().unwrap();
+            assert!((energy(&ms) - energy(&lr)).abs() < 1e-12);
+
+            let (l, r) = stereo(&ms.fold_ms_decode().unwrap());
+            assert!((l - pair[0]).abs() < 1e-12 && (r - pair[1]).abs() < 1e-12);
+        }
+
+        assert!(matches!(
+            Constant::F32(vec![1.0]).fold_ms_encode(),
+            Err(ConstantFoldingError::NotStereo)
+        ));
+        assert!(matches!(
+            Constant::I64(vec![1, 2]).fold_ms_encode(),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_stereo_width() {
+        let width = |lr: &Constant, w: f64| lr.fold_stereo_width(&Constant::F64(vec![w])).unwrap();
+
+        // Mono stays mono at any width.
+        let mono = Constant::F64(vec![0.5, 0.5]);
+        for w in [0.0, 0.5, 1.0, 1.5] {
+            let (l, r) = stereo(&width(&mono, w));
+            assert!((l - r).abs() < 1e-12);
+        }
+
+        // A hard-panned signal has equal mid and side, so its energy is kept at every width.
+        let panned = Constant::F64(vec![1.0, 0.0]);
+        for w in [0.0, 0.25, 1.0, 1.75, 2.0, 5.0] {
+            assert!((energy(&width(&panned, w)) - 1.0).abs() < 1e-12);
+        }
+
+        // 1 leaves the pair alone, and 0 collapses it to mono.
+        let (l, r) = stereo(&width(&panned, 1.0));
+        assert!((l - 1.0).abs() < 1e-12 && r.abs() < 1e-12);
+        let (l, r) = stereo(&width(&panned, 0.0));
+        assert!((l - r).abs() < 1e-12);
+
+        assert!(matches!(
+            panned.fold_stereo_width(&Constant::F64(vec![1.0, 1.0])),
+            Err(ConstantFoldingError::NotStereo)
+        ));
+        assert!(matches!(
+            panned.fold_stereo_width(&Constant::F32(vec![1.0])),
+            Err(ConstantFoldingError::IncompatibleTypes)
+        ));
+    }
+
+    #[test]
+    fn test_lerp() {
+        let a = Constant::F32(vec![0.0, 10.0]);
+        let b = Constant::F32(vec![1.0]);
+        assert_eq!(
+            a.fold_lerp(&b, &Constant::F32(vec![0.5])).unwrap(),
+            Constant::F32(vec![0.5, 5.5])
+        );
+        assert_eq!(
+            a.fold_lerp(&b, &Constant::F32(vec![0.0, 1.0])).unwrap(),
+            Constant::F32(vec![0.0, 1.0])
+        );
+
+        assert!(matches!(
+            a.fold_lerp(&b, &Constant::F64(vec![0.5])),
+            Err(ConstantFoldingError::IncompatibleTypes)
+        ));
+        let i = Constant::I64(vec![1]);
+        assert!(matches!(
+            i.fold_lerp(&i, &i),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let l = Constant::F32(vec![1.0, 2.0, 3.0]);
+        let r = Constant::F32(vec![2.0]);
+        assert_eq!(
+            l.fold_compare(&r, CompareOp::Lt).unwrap(),
+            Constant::Bool(vec![true, false, false])
+        );
+        assert_eq!(
+            l.fold_compare(&r, CompareOp::Ge).unwrap(),
+            Constant::Bool(vec![false, true, true])
+        );
+        assert_eq!(
+            l.fold_compare(&r, CompareOp::Ne).unwrap(),
+            Constant::Bool(vec![true, false, true])
+        );
+
+        // NaN compares unequal to everything, including itself.
+        let nan = Constant::F64(vec![f64::NAN]);
+        assert_eq!(
+            nan.fold_compare(&nan, CompareOp::Eq).unwrap(),
+            Constant::Bool(vec![false])
+        );
+
+        let b = Constant::Bool(vec![true, false]);
+        assert_eq!(
+            b.fold_compare(&Constant::Bool(vec![true]), CompareOp::Eq)
+                .unwrap(),
+            Constant::Bool(vec![true, false])
+        );
+        assert!(matches!(
+            b.fold_compare(&b, CompareOp::Lt),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+    }
+
+    #[test]
+    fn test_select() {
+        let cond = Constant::Bool(vec![true, false, true]);
+        assert_eq!(
+            cond.fold_select(&Constant::I64(vec![1, 2, 3]), &Constant::I64(vec![0]))
+                .unwrap(),
+            Constant::I64(vec![1, 0, 3])
+        );
+
+        assert!(matches!(
+            Constant::I64(vec![1]).fold_select(&Constant::I64(vec![1]), &Constant::I64(vec![1])),
+            Err(ConstantFoldingError::UnsupportedType)
+        ));
+        assert!(matches!(
+            cond.fold_select(&Constant::I64(vec![1, 2]), &Constant::I64(vec![1])),
+            Err(ConstantFoldingError::IncompatibleWidths)
+        ));
+    }
+
+    #[test]
+    fn test_broadcasting() {
+        let scalar = Constant::F64(vec![2.0]);
+        let vector = Constant::F64(vec![1.0, 2.0, 3.0]);
+        assert_eq!(
+            scalar.fold_mul(&vector).unwrap(),
+            Constant::F64(vec![2.0, 4.0, 6.0])
+        );
+        assert_eq!(
+            vector.fold_sub(&scalar).unwrap(),
+            Constant::F64(vec![-1.0, 0.0, 1.0])
+        );
+    }
 }
@@ -32,4 +32,20 @@ impl DataType {
     pub fn new_v_f64(width: u64) -> Self {
         Self::new_vector(PrimitiveType::F64, width)
     }
+
+    /// Set the rate, if this is a vector.
+    pub fn with_rate(self, rate: Rate) -> Self {
+        match self {
+            Self::Vector(v) => Self::Vector(v.with_rate(rate)),
+            Self::Never => Self::Never,
+        }
+    }
+
+    /// Get the vector descriptor, if this is a vector.
+    pub fn as_vector(&self) -> Option<VectorDescriptor> {
+        match self {
+            Self::Vector(v) => Some(*v),
+            Self::Never => None,
+        }
+    }
 }
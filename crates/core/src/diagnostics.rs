@@ -5,10 +5,24 @@ use indenter::indented;
 
 use crate::{OperationGraphNode, Program, SourceLoc};
 
+/// How severe is a diagnostic?
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Hash, derive_more::IsVariant)]
+pub enum DiagnosticLevel {
+    /// Purely informational, e.g. what a pass did.
+    Note,
+
+    /// Something the user should know about, but which doesn't stop compilation.
+    Warning,
+
+    /// Compilation cannot proceed.
+    Error,
+}
+
 /// A compilation diagnostic.
 ///
 /// Consists of:
 ///
+/// - A level, saying whether or not this stops compilation.
 /// - A message saying what the problem is.
 /// - A possible source location for the overall error, when it happens early enough that that makes sense.
 /// - References to nodes with descriptions of what's wrong.
@@ -16,6 +30,7 @@ use crate::{OperationGraphNode, Program, SourceLoc};
 /// Should be created through [DiagnosticBuilder].
 #[derive(Debug)]
 pub struct Diagnostic {
+    pub level: DiagnosticLevel,
     pub message: Cow<'static, str>,
     pub node_refs: Vec<DiagnosticNodeRef>,
     pub source_loc: Option<SourceLoc>,
@@ -43,13 +58,14 @@ pub struct DiagnosticBuilder {
 /// A collection of diagnostics, for eventual display to the user.
 #[derive(Debug, Default)]
 pub struct DiagnosticCollection {
-    pub errors: Vec<Diagnostic>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl DiagnosticBuilder {
     pub fn new(message: impl Into<Cow<'static, str>>, source_loc: Option<SourceLoc>) -> Self {
         Self {
             diagnostic: Diagnostic {
+                level: DiagnosticLevel::Error,
                 message: message.into(),
                 node_refs: vec![],
                 source_loc,
@@ -57,6 +73,11 @@ impl DiagnosticBuilder {
         }
     }
 
+    /// Set the level of this diagnostic.  The default is [DiagnosticLevel::Error].
+    pub fn set_level(&mut self, level: DiagnosticLevel) {
+        self.diagnostic.level = level;
+    }
+
     pub fn node_ref(&mut self, reason: impl Into<Cow<'static, str>>, node: OperationGraphNode) {
         self.diagnostic.node_refs.push(DiagnosticNodeRef {
             reason: reason.into(),
@@ -78,7 +99,12 @@ impl Display for Diagnostic {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
         use std::fmt::Write;
 
-        write!(formatter, "Error: {}", self.message)?;
+        let level = match self.level {
+            DiagnosticLevel::Note => "Note",
+            DiagnosticLevel::Warning => "Warning",
+            DiagnosticLevel::Error => "Error",
+        };
+        write!(formatter, "{}: {}", level, self.message)?;
         if let Some(loc) = self.source_loc.as_ref() {
             writeln!(formatter)?;
             write!(indented(formatter).ind(2), "{}", loc)?;
@@ -104,7 +130,17 @@ impl DiagnosticCollection {
     }
 
     pub fn add_diagnostic(&mut self, diag: Diagnostic) {
-        self.errors.push(diag);
+        self.diagnostics.push(diag);
+    }
+
+    /// Does this collection contain at least one diagnostic at [DiagnosticLevel::Error]?
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.level.is_error())
+    }
+
+    /// Iterate over the diagnostics of a given level.
+    pub fn iter_level(&self, level: DiagnosticLevel) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(move |d| d.level == level)
     }
 
     pub fn add_simple_diagnostic(
@@ -122,7 +158,7 @@ impl DiagnosticCollection {
 impl Display for DiagnosticCollection {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut first = false;
-        for e in self.errors.iter() {
+        for e in self.diagnostics.iter() {
             if first {
                 first = false;
             } else {
@@ -1,6 +1,6 @@
 use crate::SourceLoc;
 
-#[derive(Debug, derive_more::Display)]
+#[derive(Clone, Debug, derive_more::Display)]
 #[display(fmt = "To input {input}")]
 pub struct Edge {
     /// Which input does this edge connect to?
@@ -1,26 +1,40 @@
 #![allow(dead_code)]
+pub mod buffer_input;
 pub mod constant;
 pub mod data_type;
 pub mod diagnostics;
 pub mod edge;
 pub mod materialized_inputs;
 pub mod node;
+pub mod noise;
 pub mod op;
 pub mod passes;
+pub mod pattern;
 pub mod program;
+pub mod property;
 pub mod source_loc;
 pub mod state;
+pub mod structural_eq;
+pub mod target_spec;
+pub mod typed_builder;
 pub mod vector_descriptor;
 
 pub use crate::constant::*;
+pub use buffer_input::*;
 pub use data_type::*;
 pub use diagnostics::*;
 pub use edge::*;
 pub use materialized_inputs::*;
 pub use node::*;
+pub use noise::*;
 pub use op::*;
 pub use passes::*;
+pub use pattern::*;
 pub use program::*;
+pub use property::*;
 pub use source_loc::*;
 pub use state::*;
+pub use structural_eq::*;
+pub use target_spec::*;
+pub use typed_builder::*;
 pub use vector_descriptor::*;
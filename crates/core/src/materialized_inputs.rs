@@ -80,6 +80,20 @@ impl MaterializedInputs {
         ret
     }
 
+    /// Materialize only the inputs of a node which carry data.
+    ///
+    /// This leaves out edges from the start node and edges to the node's ordering input (see [Op::ordering_input]).
+    pub fn materialize_data_inputs(
+        program: &Program,
+        node: OperationGraphNode,
+    ) -> MaterializedInputs {
+        let mut ret = Self::materialize_with_filter(program, node, |x| x != program.start_node);
+        if let Some(i) = program.graph.node_weight(node).unwrap().op.ordering_input() {
+            ret.inputs.truncate(i);
+        }
+        ret
+    }
+
     /// Get the materialized input for an index.
     ///
     /// Returns an emptty slice for unused inputs.
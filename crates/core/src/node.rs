@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use crate::*;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Node {
     pub op: Op,
 
@@ -18,6 +18,122 @@ pub enum BinOp {
 
     #[display(fmt = "/")]
     Div,
+
+    /// Logical and of booleans.
+    #[display(fmt = "&&")]
+    LogicalAnd,
+
+    /// Logical or of booleans.
+    #[display(fmt = "||")]
+    LogicalOr,
+
+    /// Bitwise and of integers.
+    #[display(fmt = "&")]
+    BitAnd,
+
+    /// Bitwise or of integers.
+    #[display(fmt = "|")]
+    BitOr,
+
+    /// Bitwise xor of integers.
+    #[display(fmt = "^")]
+    BitXor,
+
+    /// Shift an integer left.
+    #[display(fmt = "<<")]
+    Shl,
+
+    /// Shift an integer right, preserving the sign.
+    #[display(fmt = ">>")]
+    Shr,
+}
+
+/// Unary math functions.
+#[derive(
+    Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, derive_more::Display, derive_more::IsVariant,
+)]
+pub enum UnaryOp {
+    /// Square root of a float.
+    #[display(fmt = "sqrt")]
+    Sqrt,
+
+    /// `e` raised to a float.
+    #[display(fmt = "exp")]
+    Exp,
+
+    /// Natural log of a float.
+    #[display(fmt = "log")]
+    Log,
+
+    /// Absolute value of an integer or float.
+    #[display(fmt = "abs")]
+    Abs,
+
+    /// Round a float towards negative infinity.
+    #[display(fmt = "floor")]
+    Floor,
+
+    /// Round a float towards positive infinity.
+    #[display(fmt = "ceil")]
+    Ceil,
+
+    /// Round a float to the nearest integer, with halfway cases going away from zero.
+    #[display(fmt = "round")]
+    Round,
+
+    /// Round a float towards zero.
+    #[display(fmt = "trunc")]
+    Trunc,
+
+    /// The fractional part of a float, `x - floor(x)`.
+    ///
+    /// Unlike Rust's `fract`, this is never negative, which is what phase accumulators and wavetable indexing want.
+    #[display(fmt = "fract")]
+    Fract,
+}
+
+/// Comparisons between two values, producing booleans.
+#[derive(
+    Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, derive_more::Display, derive_more::IsVariant,
+)]
+pub enum CompareOp {
+    #[display(fmt = "==")]
+    Eq,
+
+    #[display(fmt = "!=")]
+    Ne,
+
+    #[display(fmt = "<")]
+    Lt,
+
+    #[display(fmt = "<=")]
+    Le,
+
+    #[display(fmt = ">")]
+    Gt,
+
+    #[display(fmt = ">=")]
+    Ge,
+}
+
+/// How to round when converting floats to integers.
+///
+/// Backends differ in what a bare conversion does, so conversions always name one of these.
+#[derive(
+    Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, derive_more::Display, derive_more::IsVariant,
+)]
+pub enum RoundingMode {
+    /// Round towards zero.
+    #[display(fmt = "trunc")]
+    Trunc,
+
+    /// Round towards negative infinity.
+    #[display(fmt = "floor")]
+    Floor,
+
+    /// Round to the nearest integer, with halfway cases going to the even one.
+    #[display(fmt = "nearest_even")]
+    NearestEven,
 }
 
 /// Kinds of operation associated with a node.
@@ -28,12 +144,55 @@ pub enum Op {
 
     Negate,
 
+    /// Logical not of a boolean.
+    LogicalNot,
+
+    /// Bitwise not of an integer.
+    BitNot,
+
     BinOp(BinOp),
 
+    UnaryOp(UnaryOp),
+
+    /// Compare the two inputs, producing a bool vector.
+    Compare(CompareOp),
+
+    /// Choose between inputs 1 and 2 per element, according to the bool condition on input 0.
+    ///
+    /// Input 1 is chosen where the condition is true.
+    Select,
+
+    /// Linearly interpolate between inputs 0 and 1 by input 2, as `a + (b - a) * t`.
+    ///
+    /// All three inputs are floats of the same type, and broadcast together.
+    Lerp,
+
+    /// Encode a stereo pair, a float vector of width 2 holding left and right, into mid and side.
+    ///
+    /// This is `mid = (l + r) / sqrt(2)` and `side = (l - r) / sqrt(2)`.  The transform is orthonormal, so it preserves
+    /// energy, and a mono signal has no side.  See [Constant::fold_ms_encode].
+    MsEncode,
+
+    /// Decode mid and side back into left and right, undoing [Op::MsEncode].
+    MsDecode,
+
+    /// Change the width of the stereo pair on input 0 by the scalar on input 1.
+    ///
+    /// A width of 0 is mono, 1 leaves the pair alone, and 2 keeps only the side.  Widths outside `[0, 2]` are clamped.
+    /// See [Constant::fold_stereo_width] for the exact math, which keeps the energy of uncorrelated mid and side.
+    StereoWidth,
+
     /// Read the given input.
     #[display(fmt = "ReadInput({_0})")]
     ReadInput(usize),
 
+    /// Read a frame of the given buffer input, at the index on input 0.
+    ///
+    /// The index is a scalar i64, and wraps modulo the length of the buffer, so that -1 is the last frame.  Reads are
+    /// never out of bounds.
+    #[display(fmt = "ReadInputIndexed({_0})")]
+    ReadInputIndexed(usize),
+
     /// Write the given output.
     #[display(fmt = "WriteOutput({_0})")]
     WriteOutput(usize),
@@ -42,6 +201,43 @@ pub enum Op {
     #[display(fmt = "ReadProperty({_0})")]
     ReadProperty(usize),
 
+    /// Read the value written to the given state by its [Op::FeedbackWrite] on the previous sample.
+    ///
+    /// On the first sample, this reads zero.  Together with [Op::FeedbackWrite], this allows one-sample feedback loops
+    /// without a cycle in the graph.
+    #[display(fmt = "FeedbackRead({_0})")]
+    FeedbackRead(usize),
+
+    /// Write the value which the next sample's [Op::FeedbackRead] of the given state will see.
+    ///
+    /// Input 0 is the value.  Input 1 carries no data: [crate::insert_start_final_edges] connects every read of the
+    /// state to it, so that the reads of this sample see the previous sample's value rather than this one.
+    #[display(fmt = "FeedbackWrite({_0})")]
+    FeedbackWrite(usize),
+
+    /// A phase accumulator, whose phase lives in the given state.
+    ///
+    /// Outputs the phase, in `[0, 1)`, then advances it by the frequency in Hz on input 0 divided by the sample rate.
+    /// The phase starts at 0.  The state must be a float state of length 1, and owned by this node alone.
+    #[display(fmt = "Phasor({_0})")]
+    Phasor(usize),
+
+    /// A sine oscillator, whose phase lives in the given state.
+    ///
+    /// Outputs `sin(2 * pi * (phase + offset))`, where the offset is input 1 in cycles, and advances the phase like
+    /// [Op::Phasor] with the frequency on input 0.
+    #[display(fmt = "SinOsc({_0})")]
+    SinOsc(usize),
+
+    /// Generate noise from the given state, which holds the generator's counters.
+    ///
+    /// Outputs uniformly distributed i64s of the state's width, computed by [noise_step], so that every backend
+    /// produces the same stream.  Cast and scale the output to get floats.  The state must be an i64 state of length 1,
+    /// owned by this node alone.  The state's initial value is the seed, one counter per lane; see
+    /// [Program::set_state_initial].
+    #[display(fmt = "Noise({_0})")]
+    Noise(usize),
+
     /// Read the clock, an i64 integer that increments every sample.
     Clock,
 
@@ -50,10 +246,16 @@ pub enum Op {
 
     /// Cast the only input to the given primitive type.
     ///
-    /// We don't perform implicit casts because it is important to always know where they happen.
+    /// We don't perform implicit casts because it is important to always know where they happen.  Casts from floats
+    /// to integers truncate; use [Op::FloatToInt] to pick another rounding mode.  See [Constant::fold_cast] for the
+    /// exact semantics.
     #[display(fmt = "cast({})", _0)]
     Cast(PrimitiveType),
 
+    /// Convert a float to an i64, rounding as specified.
+    #[display(fmt = "float_to_int({})", _0)]
+    FloatToInt(RoundingMode),
+
     /// The synthetic start node is used to have a single entry node, rather than n entry nodes.
     ///
     /// Doesn't carry data.
@@ -68,6 +270,10 @@ pub enum Op {
     Final,
 }
 
+/// The primitives which can't index a buffer input in [Op::ReadInputIndexed]; indices are integers.
+pub const INDEX_DENIED_PRIMITIVES: &[PrimitiveType] =
+    &[PrimitiveType::Bool, PrimitiveType::F32, PrimitiveType::F64];
+
 /// A descriptor for an operation, which describes the inputs and outputs for the type checker and opptimization passes.
 #[derive(Clone, Debug)]
 pub struct OpDescriptor {
@@ -105,16 +311,43 @@ pub struct InputDescriptor {
 
 fn binop_to_descriptor(o: BinOp) -> OpDescriptor {
     OpDescriptor {
-        commutative: [BinOp::Add, BinOp::Mul].contains(&o),
+        commutative: [
+            BinOp::Add,
+            BinOp::Mul,
+            BinOp::LogicalAnd,
+            BinOp::LogicalOr,
+            BinOp::BitAnd,
+            BinOp::BitOr,
+            BinOp::BitXor,
+        ]
+        .contains(&o),
 
-        inputs: Cow::Borrowed(&[InputDescriptor {
+        inputs: Cow::Owned(vec![InputDescriptor {
             input_kind: InputKind::Data,
-            denied_primitives: Some(Cow::Borrowed(&[PrimitiveType::Bool])),
+            denied_primitives: Some(Cow::Borrowed(o.denied_primitives())),
         }]),
     }
 }
 
 impl Op {
+    /// Does this op do something other than produce a value?
+    ///
+    /// Such nodes must be kept even if nothing consumes their output.
+    pub fn is_side_effecting(&self) -> bool {
+        matches!(self, Op::WriteOutput(_) | Op::FeedbackWrite(_) | Op::Final)
+    }
+
+    /// The input which only orders this node after others, if any.
+    ///
+    /// Edges to this input carry no data, so type inference and pattern matching ignore them.  It is always the last
+    /// input.
+    pub fn ordering_input(&self) -> Option<usize> {
+        match self {
+            Op::FeedbackWrite(_) => Some(1),
+            _ => None,
+        }
+    }
+
     pub fn get_descriptor(&self) -> Cow<'static, OpDescriptor> {
         match *self {
             Op::Start => Cow::Borrowed(&OpDescriptor {
@@ -123,23 +356,152 @@ impl Op {
                 inputs: Cow::Borrowed(&[]),
             }),
             // these must have a connection from the start node.
-            Op::ReadInput(_) | Op::ReadProperty(_) | Op::Constant(_) | Op::Clock | Op::Sr => {
+            Op::ReadInput(_)
+            | Op::ReadProperty(_)
+            | Op::FeedbackRead(_)
+            | Op::Noise(_)
+            | Op::Constant(_)
+            | Op::Clock
+            | Op::Sr => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::PureDependency,
+                    denied_primitives: None,
+                }]),
+            }),
+            Op::BinOp(o) => Cow::Owned(binop_to_descriptor(o)),
+            Op::Negate => Cow::Owned(OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(&[PrimitiveType::Bool])),
+                }]),
+            }),
+            Op::LogicalNot => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(&[
+                        PrimitiveType::I64,
+                        PrimitiveType::F32,
+                        PrimitiveType::F64,
+                    ])),
+                }]),
+            }),
+            Op::BitNot => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(&[
+                        PrimitiveType::Bool,
+                        PrimitiveType::F32,
+                        PrimitiveType::F64,
+                    ])),
+                }]),
+            }),
+            Op::UnaryOp(o) => Cow::Owned(OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Owned(vec![InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(o.denied_primitives())),
+                }]),
+            }),
+            Op::Compare(o) => Cow::Owned(OpDescriptor {
+                commutative: o.is_eq() || o.is_ne(),
+
+                inputs: Cow::Owned(vec![InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(o.denied_primitives())),
+                }]),
+            }),
+            Op::Lerp | Op::MsEncode | Op::MsDecode | Op::StereoWidth => {
                 Cow::Borrowed(&OpDescriptor {
                     commutative: false,
 
                     inputs: Cow::Borrowed(&[InputDescriptor {
-                        input_kind: InputKind::PureDependency,
-                        denied_primitives: None,
+                        input_kind: InputKind::Data,
+                        denied_primitives: Some(Cow::Borrowed(&[
+                            PrimitiveType::Bool,
+                            PrimitiveType::I64,
+                        ])),
                     }]),
                 })
             }
-            Op::BinOp(o) => Cow::Owned(binop_to_descriptor(o)),
-            Op::Negate => Cow::Owned(OpDescriptor {
+            Op::Phasor(_) => Cow::Borrowed(&OpDescriptor {
                 commutative: false,
 
                 inputs: Cow::Borrowed(&[InputDescriptor {
                     input_kind: InputKind::Data,
-                    denied_primitives: Some(Cow::Borrowed(&[PrimitiveType::Bool])),
+                    denied_primitives: Some(Cow::Borrowed(&[
+                        PrimitiveType::Bool,
+                        PrimitiveType::I64,
+                    ])),
+                }]),
+            }),
+            Op::SinOsc(_) => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: Some(Cow::Borrowed(&[
+                            PrimitiveType::Bool,
+                            PrimitiveType::I64,
+                        ])),
+                    },
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: Some(Cow::Borrowed(&[
+                            PrimitiveType::Bool,
+                            PrimitiveType::I64,
+                        ])),
+                    },
+                ]),
+            }),
+            Op::Select => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: Some(Cow::Borrowed(&[
+                            PrimitiveType::I64,
+                            PrimitiveType::F32,
+                            PrimitiveType::F64,
+                        ])),
+                    },
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: None,
+                    },
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: None,
+                    },
+                ]),
+            }),
+            Op::ReadInputIndexed(_) => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(INDEX_DENIED_PRIMITIVES)),
+                }]),
+            }),
+            Op::FloatToInt(_) => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[InputDescriptor {
+                    input_kind: InputKind::Data,
+                    denied_primitives: Some(Cow::Borrowed(&[
+                        PrimitiveType::Bool,
+                        PrimitiveType::I64,
+                    ])),
                 }]),
             }),
             // The difference from Negate is that cast allows all inputs.
@@ -159,6 +521,21 @@ impl Op {
                     denied_primitives: None,
                 }]),
             }),
+            // The second input orders the state's reads before the write.
+            Op::FeedbackWrite(_) => Cow::Borrowed(&OpDescriptor {
+                commutative: false,
+
+                inputs: Cow::Borrowed(&[
+                    InputDescriptor {
+                        input_kind: InputKind::Data,
+                        denied_primitives: None,
+                    },
+                    InputDescriptor {
+                        input_kind: InputKind::PureDependency,
+                        denied_primitives: None,
+                    },
+                ]),
+            }),
             // Difference here is that final inputs are pure dependerncies, and of course it doesn't have edges to
             // itself.
             Op::Final => Cow::Borrowed(&OpDescriptor {
@@ -174,6 +551,19 @@ impl Op {
 }
 
 impl BinOp {
+    /// The primitives this operation can't be applied to.
+    pub fn denied_primitives(&self) -> &'static [PrimitiveType] {
+        match self {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => &[PrimitiveType::Bool],
+            BinOp::LogicalAnd | BinOp::LogicalOr => {
+                &[PrimitiveType::I64, PrimitiveType::F32, PrimitiveType::F64]
+            }
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
+                &[PrimitiveType::Bool, PrimitiveType::F32, PrimitiveType::F64]
+            }
+        }
+    }
+
     /// Fold two constants according to the operation this BinOp represents.
     fn fold_constants(
         &self,
@@ -185,6 +575,69 @@ impl BinOp {
             BinOp::Sub => left.fold_sub(right),
             BinOp::Mul => left.fold_mul(right),
             BinOp::Div => left.fold_div(right),
+            BinOp::LogicalAnd => left.fold_logical_and(right),
+            BinOp::LogicalOr => left.fold_logical_or(right),
+            BinOp::BitAnd => left.fold_bit_and(right),
+            BinOp::BitOr => left.fold_bit_or(right),
+            BinOp::BitXor => left.fold_bit_xor(right),
+            BinOp::Shl => left.fold_shl(right),
+            BinOp::Shr => left.fold_shr(right),
+        }
+    }
+}
+
+impl UnaryOp {
+    /// The primitives this operation can't be applied to.
+    pub fn denied_primitives(&self) -> &'static [PrimitiveType] {
+        match self {
+            UnaryOp::Sqrt
+            | UnaryOp::Exp
+            | UnaryOp::Log
+            | UnaryOp::Floor
+            | UnaryOp::Ceil
+            | UnaryOp::Round
+            | UnaryOp::Trunc
+            | UnaryOp::Fract => &[PrimitiveType::Bool, PrimitiveType::I64],
+            UnaryOp::Abs => &[PrimitiveType::Bool],
+        }
+    }
+
+    /// Fold a constant according to the operation this UnaryOp represents.
+    pub fn fold_constant(&self, value: &Constant) -> Result<Constant, ConstantFoldingError> {
+        match self {
+            UnaryOp::Sqrt => value.fold_sqrt(),
+            UnaryOp::Exp => value.fold_exp(),
+            UnaryOp::Log => value.fold_log(),
+            UnaryOp::Abs => value.fold_abs(),
+            UnaryOp::Floor => value.fold_floor(),
+            UnaryOp::Ceil => value.fold_ceil(),
+            UnaryOp::Round => value.fold_round(),
+            UnaryOp::Trunc => value.fold_trunc(),
+            UnaryOp::Fract => value.fold_fract(),
+        }
+    }
+}
+
+impl CompareOp {
+    /// The primitives this comparison can't be applied to.
+    ///
+    /// Bools can be tested for equality, but aren't ordered.
+    pub fn denied_primitives(&self) -> &'static [PrimitiveType] {
+        match self {
+            CompareOp::Eq | CompareOp::Ne => &[],
+            CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge => &[PrimitiveType::Bool],
+        }
+    }
+
+    /// Apply this comparison to two values.
+    pub fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            CompareOp::Eq => left == right,
+            CompareOp::Ne => left != right,
+            CompareOp::Lt => left < right,
+            CompareOp::Le => left <= right,
+            CompareOp::Gt => left > right,
+            CompareOp::Ge => left >= right,
         }
     }
 }
@@ -0,0 +1,134 @@
+//! Remove nodes whose results are never used.
+//!
+//! A node is live if it is side-effecting (see [Op::is_side_effecting]) or if there is a path from it to a live node.
+//! Everything else is dead, and is removed along with its edges.  The start node is always kept.
+//!
+//! Since writes are side-effecting, this pass can run either before or after [insert_start_final_edges].
+use std::collections::HashSet;
+
+use petgraph::prelude::*;
+
+use crate::*;
+
+/// Run dead code elimination, returning the number of nodes removed.
+///
+/// If any nodes are removed, a note saying how many is pushed to the diagnostics.
+pub fn dead_code_elimination(
+    program: &mut Program,
+    diagnostics: &mut DiagnosticCollection,
+) -> usize {
+    let mut live = HashSet::new();
+    let mut stack = program
+        .graph
+        .node_indices()
+        .filter(|n| {
+            program
+                .graph
+                .node_weight(*n)
+                .unwrap()
+                .op
+                .is_side_effecting()
+        })
+        .collect::<Vec<_>>();
+    stack.push(program.start_node);
+
+    while let Some(n) = stack.pop() {
+        if !live.insert(n) {
+            continue;
+        }
+
+        // Ordering edges don't make their source live; a read nothing consumes is still dead.
+        let ordering_input = program.graph.node_weight(n).unwrap().op.ordering_input();
+        stack.extend(
+            program
+                .graph
+                .edges_directed(n, Direction::Incoming)
+                .filter(|e| Some(e.weight().input) != ordering_input)
+                .map(|e| e.source()),
+        );
+    }
+
+    let dead = program
+        .graph
+        .node_indices()
+        .filter(|n| !live.contains(n))
+        .collect::<Vec<_>>();
+
+    for n in dead.iter() {
+        program.graph.remove_node(*n);
+    }
+
+    if !dead.is_empty() {
+        let mut builder = DiagnosticBuilder::new(
+            format!("Dead code elimination removed {} nodes", dead.len()),
+            None,
+        );
+        builder.set_level(DiagnosticLevel::Note);
+        diagnostics.add_diagnostic(builder.build(program));
+    }
+
+    dead.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let mut prog = Program::new();
+        let i = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let o = prog.add_output(PrimitiveType::F32, 1).unwrap();
+
+        let read = prog.op_read_input_node(i, None).unwrap();
+        let write = prog.op_write_output_node(o, None).unwrap();
+        prog.connect(read, write, 0, None).unwrap();
+
+        // A chain hanging off a live node, which goes nowhere.
+        let neg1 = prog.op_negate_node(None).unwrap();
+        let neg2 = prog.op_negate_node(None).unwrap();
+        prog.connect(read, neg1, 0, None).unwrap();
+        prog.connect(neg1, neg2, 0, None).unwrap();
+
+        // And an orphaned constant.
+        let constant = prog
+            .op_constant_node(Constant::F32(vec![1.0]), None)
+            .unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert_eq!(dead_code_elimination(&mut prog, &mut diags), 3);
+
+        for n in [neg1, neg2, constant] {
+            assert!(!prog.graph.contains_node(n), "{}", prog.graphviz());
+        }
+        for n in [prog.start_node, prog.final_node, read, write] {
+            assert!(prog.graph.contains_node(n), "{}", prog.graphviz());
+        }
+
+        assert_eq!(diags.iter_level(DiagnosticLevel::Note).count(), 1);
+        assert!(!diags.has_errors());
+
+        // Running again finds nothing, and says nothing.
+        let mut diags = DiagnosticCollection::new();
+        assert_eq!(dead_code_elimination(&mut prog, &mut diags), 0);
+        assert!(diags.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_writes_are_kept_before_final_edges_exist() {
+        let mut prog = Program::new();
+        let o = prog.add_output(PrimitiveType::F32, 1).unwrap();
+        let constant = prog
+            .op_constant_node(Constant::F32(vec![1.0]), None)
+            .unwrap();
+        let write = prog.op_write_output_node(o, None).unwrap();
+        prog.connect(constant, write, 0, None).unwrap();
+
+        assert_eq!(
+            dead_code_elimination(&mut prog, &mut DiagnosticCollection::new()),
+            0
+        );
+        assert!(prog.graph.contains_node(constant));
+    }
+}
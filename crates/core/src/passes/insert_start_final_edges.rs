@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use petgraph::algo::has_path_connecting;
 use petgraph::prelude::*;
 
 use crate::*;
@@ -28,9 +31,30 @@ fn implicit_edge_kind(o: &Op) -> ImplicitEdgeKind {
     use self::ImplicitEdgeKind::*;
     match o {
         Op::Start | Op::Final => None,
-        Op::ReadInput(_) | Op::Clock | Op::Sr | Op::ReadProperty(_) | Op::Constant(_) => Start,
-        Op::Negate | Op::BinOp(_) | Op::Cast(_) => None,
-        Op::WriteOutput(_) => Final,
+        Op::ReadInput(_)
+        | Op::Clock
+        | Op::Sr
+        | Op::ReadProperty(_)
+        | Op::FeedbackRead(_)
+        | Op::Noise(_)
+        | Op::Constant(_) => Start,
+        Op::Negate
+        | Op::LogicalNot
+        | Op::BitNot
+        | Op::BinOp(_)
+        | Op::UnaryOp(_)
+        | Op::Compare(_)
+        | Op::Select
+        | Op::Lerp
+        | Op::MsEncode
+        | Op::MsDecode
+        | Op::StereoWidth
+        | Op::Phasor(_)
+        | Op::SinOsc(_)
+        | Op::Cast(_)
+        | Op::FloatToInt(_)
+        | Op::ReadInputIndexed(_) => None,
+        Op::WriteOutput(_) | Op::FeedbackWrite(_) => Final,
     }
 }
 
@@ -43,9 +67,38 @@ fn node_has_edge_from_kind<'a>(
         .map(|e| &program.graph.node_weight(e.source()).unwrap().op)
         .any(pred)
 }
+/// Find the pairs of feedback reads and writes which need an ordering edge, as `(read, write)`.
+///
+/// A read which already feeds its write directly needs no edge.
+fn feedback_ordering_pairs(program: &Program) -> Vec<(OperationGraphNode, OperationGraphNode)> {
+    let mut writes: HashMap<usize, Vec<OperationGraphNode>> = HashMap::new();
+    for n in program.graph.node_indices() {
+        if let Op::FeedbackWrite(s) = program.graph.node_weight(n).unwrap().op {
+            writes.entry(s).or_default().push(n);
+        }
+    }
+
+    let mut pairs = vec![];
+    for read in program.graph.node_indices() {
+        let Op::FeedbackRead(s) = program.graph.node_weight(read).unwrap().op else {
+            continue;
+        };
+        for write in writes.get(&s).into_iter().flatten() {
+            if !program.graph.contains_edge(read, *write) {
+                pairs.push((read, *write));
+            }
+        }
+    }
+
+    pairs
+}
+
 /// Run the pass which inserts edges from the start node to all initial nodes in the graph, and inserts edges from all
 /// the final nodes to the implicit final node.
 ///
+/// This pass also connects every [Op::FeedbackRead] to the ordering input of the [Op::FeedbackWrite] of the same
+/// state (see [Op::ordering_input]), so that any order which respects the edges reads a state before writing it.
+///
 /// If This pass fails, it has pushed the appropriate diagnostics to the builder already.
 pub fn insert_start_final_edges(
     program: &mut Program,
@@ -113,6 +166,21 @@ pub fn insert_start_final_edges(
         }
     }
 
+    // If a write reaches a read of its own state, the ordering edge would make a cycle.
+    let ordering_pairs = feedback_ordering_pairs(program);
+    for (read, write) in ordering_pairs.iter() {
+        if has_path_connecting(&program.graph, *write, *read, None) {
+            let mut db = DiagnosticBuilder::new(
+                "A feedback read must not depend on the write of the same state",
+                None,
+            );
+            db.node_ref("The read", *read);
+            db.node_ref("The write", *write);
+            diagnostics.add_diagnostic(db.build(program));
+            validation_succeeded = false;
+        }
+    }
+
     if !validation_succeeded {
         return Err(InsertStartFinalEdgesError);
     }
@@ -146,6 +214,24 @@ pub fn insert_start_final_edges(
         }
     }
 
+    for (read, write) in ordering_pairs {
+        let input = program
+            .graph
+            .node_weight(write)
+            .unwrap()
+            .op
+            .ordering_input()
+            .expect("Feedback writes have an ordering input");
+        program.graph.add_edge(
+            read,
+            write,
+            Edge {
+                input,
+                source_loc: None,
+            },
+        );
+    }
+
     Ok(())
 }
 
@@ -160,11 +246,13 @@ mod tests {
         let mut program = Program::new();
         let input_index = program.add_input(PrimitiveType::F32, 3).unwrap();
         let output_index = program.add_output(PrimitiveType::F32, 3).unwrap();
-        let prop_index = program.add_property(PrimitiveType::F32).unwrap();
+        let prop_index = program
+            .add_property(PropertyDescriptor::new("prop", PrimitiveType::F32))
+            .unwrap();
 
         // These nodes should have an edge from the start node.  Put them in an array, then reduce that array into an
         // add node, then connect that add node to the ones that should have an edge to the final node.
-        let starts = vec![
+        let starts = [
             program.op_read_input_node(input_index, None).unwrap(),
             program
                 .op_constant_node(Constant::F32(vec![0.0, 0.0, 0.0]), None)
@@ -187,7 +275,7 @@ mod tests {
             })
             .unwrap();
 
-        let ends = vec![program.op_write_output_node(output_index, None).unwrap()];
+        let ends = [program.op_write_output_node(output_index, None).unwrap()];
 
         for n in ends.iter().cloned() {
             program.connect(final_add, n, 0, None).unwrap();
@@ -230,4 +318,57 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_feedback_ordering() {
+        let mut program = Program::new();
+        let input = program.add_input(PrimitiveType::F32, 1).unwrap();
+        let output = program.add_output(PrimitiveType::F32, 1).unwrap();
+        let state = program.add_state(PrimitiveType::F32, 1, 1).unwrap();
+
+        // The write doesn't depend on the read, so nothing but the ordering edge keeps it after the read.
+        let read_input = program.op_read_input_node(input, None).unwrap();
+        let read = program.op_feedback_read_node(state, None).unwrap();
+        let unused_read = program.op_feedback_read_node(state, None).unwrap();
+        let write_output = program.op_write_output_node(output, None).unwrap();
+        let write = program.op_feedback_write_node(state, None).unwrap();
+        program.connect(read, write_output, 0, None).unwrap();
+        program.connect(read_input, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut program, &mut diags).unwrap();
+        let gv = program.graphviz();
+        assert!(program.graph.contains_edge(read, write), "{}", gv);
+        assert!(program.graph.contains_edge(unused_read, write), "{}", gv);
+
+        let order = program.topological_sort().unwrap();
+        let pos = |n| order.iter().position(|x| *x == n).unwrap();
+        assert!(pos(read) < pos(write), "{}", gv);
+
+        // Running the pass again adds nothing, and the ordering edge isn't an input as far as types are concerned.
+        let edges = program.graph.edge_count();
+        insert_start_final_edges(&mut program, &mut diags).unwrap();
+        assert_eq!(program.graph.edge_count(), edges);
+        type_inference(&program, &mut diags).unwrap();
+        assert!(diags.diagnostics.is_empty(), "{}", diags);
+
+        // A read only the ordering edge uses is still dead.
+        assert_eq!(dead_code_elimination(&mut program, &mut diags), 1);
+        assert!(!program.graph.contains_node(unused_read));
+        assert!(program.graph.contains_node(read));
+
+        // If the write feeds a read of its state, ordering them would make a cycle.
+        let mut program = Program::new();
+        let state = program.add_state(PrimitiveType::F32, 1, 1).unwrap();
+        let constant = program
+            .op_constant_node(Constant::F32(vec![1.0]), None)
+            .unwrap();
+        let read = program.op_feedback_read_node(state, None).unwrap();
+        let write = program.op_feedback_write_node(state, None).unwrap();
+        program.connect(constant, write, 0, None).unwrap();
+        program.connect(write, read, 0, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        assert!(insert_start_final_edges(&mut program, &mut diags).is_err());
+        assert!(!program.graph.contains_edge(read, write));
+    }
 }
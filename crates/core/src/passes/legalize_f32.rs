@@ -0,0 +1,406 @@
+//! Legalize a program so that it performs no f64 math.
+//!
+//! Some backends can only do f32 math.  Rather than forcing programs to be written twice, this pass rewrites all f64
+//! computation to f32:
+//!
+//! - f64 constants are narrowed.
+//! - Casts to f64 become casts to f32.
+//! - Reads of f64 inputs and properties get an explicit cast to f32 after them, since the host still hands us f64.
+//! - Writes to f64 outputs get an explicit cast back to f64 before them, for the same reason.
+//! - States holding f64 are narrowed, since they are internal to the program.
+//!
+//! Everything else (binary operations, negation, etc.) derives its type from its inputs, and so follows along without
+//! being touched.  Warnings are emitted wherever precision loss might matter.  Types must be inferred again after this
+//! pass runs.
+//!
+//! Running the pass again is a no-op: reads already followed by a cast to f32 and writes already preceded by a cast to
+//! f64 are left alone.  Nodes which are malformed, e.g. reading an input which doesn't exist, are also left alone for
+//! type inference to report.
+use petgraph::prelude::*;
+
+use crate::*;
+
+/// Build a warning pointing at a node, and push it to the collection.
+fn warn_at(
+    program: &Program,
+    diagnostics: &mut DiagnosticCollection,
+    node: OperationGraphNode,
+    message: String,
+    reason: &'static str,
+) {
+    let mut builder = DiagnosticBuilder::new(message, None);
+    builder.set_level(DiagnosticLevel::Warning);
+    builder.node_ref(reason, node);
+    diagnostics.add_diagnostic(builder.build(program));
+}
+
+/// Insert a cast after `node`, moving all of `node`'s outgoing edges so that they come from the cast instead.
+fn insert_cast_after(program: &mut Program, node: OperationGraphNode, to_ty: PrimitiveType) {
+    let outgoing = program
+        .graph
+        .edges_directed(node, Direction::Outgoing)
+        .map(|e| (e.id(), e.target(), e.weight().clone()))
+        .collect::<Vec<_>>();
+
+    let source_loc = program.cloned_source_loc(node);
+    let cast = program.graph.add_node(Node {
+        op: Op::Cast(to_ty),
+        source_loc: source_loc.clone(),
+    });
+
+    for (id, target, weight) in outgoing {
+        program.graph.remove_edge(id);
+        program.graph.add_edge(cast, target, weight);
+    }
+
+    program.graph.add_edge(
+        node,
+        cast,
+        Edge {
+            input: 0,
+            source_loc,
+        },
+    );
+}
+
+/// Insert a cast before `node`, which has only one input, moving all of `node`'s incoming edges to the cast.
+///
+/// If some edge goes to another input the node is malformed, and is left alone.
+fn insert_cast_before(program: &mut Program, node: OperationGraphNode, to_ty: PrimitiveType) {
+    let incoming = program
+        .graph
+        .edges_directed(node, Direction::Incoming)
+        .map(|e| (e.id(), e.source(), e.weight().clone()))
+        .collect::<Vec<_>>();
+
+    if incoming.iter().any(|(_, _, weight)| weight.input != 0) {
+        return;
+    }
+
+    let source_loc = program.cloned_source_loc(node);
+    let cast = program.graph.add_node(Node {
+        op: Op::Cast(to_ty),
+        source_loc: source_loc.clone(),
+    });
+
+    for (id, source, weight) in incoming {
+        program.graph.remove_edge(id);
+        program.graph.add_edge(source, cast, weight);
+    }
+
+    program.graph.add_edge(
+        cast,
+        node,
+        Edge {
+            input: 0,
+            source_loc,
+        },
+    );
+}
+
+/// Does `node` feed only a cast to `ty`?
+fn only_feeds_cast(program: &Program, node: OperationGraphNode, ty: PrimitiveType) -> bool {
+    let mut outgoing = program.graph.edges_directed(node, Direction::Outgoing);
+    match (outgoing.next(), outgoing.next()) {
+        (Some(e), None) => program.graph[e.target()].op == Op::Cast(ty),
+        _ => false,
+    }
+}
+
+/// Is `node` fed only by a cast to `ty`?
+fn only_fed_by_cast(program: &Program, node: OperationGraphNode, ty: PrimitiveType) -> bool {
+    let mut incoming = program.graph.edges_directed(node, Direction::Incoming);
+    match (incoming.next(), incoming.next()) {
+        (Some(e), None) => program.graph[e.source()].op == Op::Cast(ty),
+        _ => false,
+    }
+}
+
+/// Does `node` feed only writes to f64 outputs?  Casts to f64 doing so are widening back out at the boundary, and must
+/// be kept.
+fn only_feeds_f64_outputs(program: &Program, node: OperationGraphNode) -> bool {
+    let mut outgoing = program
+        .graph
+        .edges_directed(node, Direction::Outgoing)
+        .peekable();
+    outgoing.peek().is_some()
+        && outgoing.all(|e| match program.graph[e.target()].op {
+            Op::WriteOutput(o) => {
+                program.outputs.get(o).map(|x| x.primitive) == Some(PrimitiveType::F64)
+            }
+            _ => false,
+        })
+}
+
+/// Rewrite all f64 computation in the program to f32.
+///
+/// This pass can't fail, but pushes warnings to the diagnostics.  It may be run before or after
+/// [insert_start_final_edges], but type inference must be run again afterwards.
+pub fn legalize_to_f32(program: &mut Program, diagnostics: &mut DiagnosticCollection) {
+    let nodes = program.graph.node_indices().collect::<Vec<_>>();
+
+    // Only needed to tell which casts lose precision.  If inference fails, assume they all might.
+    let types = type_inference(program, &mut DiagnosticCollection::new()).ok();
+
+    let is_f64 = |prim: Option<PrimitiveType>| prim == Some(PrimitiveType::F64);
+
+    for node in nodes {
+        let op = &program.graph.node_weight(node).unwrap().op;
+
+        match op {
+            Op::Constant(Constant::F64(vals)) => {
+                let narrowed = vals.iter().map(|x| *x as f32).collect::<Vec<_>>();
+                let lossy = vals
+                    .iter()
+                    .zip(narrowed.iter())
+                    .any(|(wide, narrow)| !wide.is_nan() && *narrow as f64 != *wide);
+
+                program.graph.node_weight_mut(node).unwrap().op =
+                    Op::Constant(Constant::F32(narrowed));
+
+                if lossy {
+                    warn_at(
+                        program,
+                        diagnostics,
+                        node,
+                        "f64 constant cannot be represented exactly as f32".to_string(),
+                        "This constant loses precision",
+                    );
+                }
+            }
+            Op::Cast(PrimitiveType::F64) if !only_feeds_f64_outputs(program, node) => {
+                // Everything float which feeds the cast is narrowed, and warned about, elsewhere.  So the cast itself
+                // only loses precision if it is from an integer.
+                let from_integer =
+                    program
+                        .graph
+                        .edges_directed(node, Direction::Incoming)
+                        .any(|e| match &types {
+                            Some(t) => {
+                                t.get_type(e.source())
+                                    .and_then(|x| x.as_vector())
+                                    .map(|x| x.primitive)
+                                    == Some(PrimitiveType::I64)
+                            }
+                            None => true,
+                        });
+
+                program.graph.node_weight_mut(node).unwrap().op = Op::Cast(PrimitiveType::F32);
+                if from_integer {
+                    warn_at(
+                        program,
+                        diagnostics,
+                        node,
+                        "Cast of an integer to f64 was legalized to a cast to f32, which may lose precision"
+                            .to_string(),
+                        "This cast",
+                    );
+                }
+            }
+            Op::ReadInput(i)
+                if is_f64(program.inputs.get(*i).map(|x| x.primitive))
+                    && !only_feeds_cast(program, node, PrimitiveType::F32) =>
+            {
+                let i = *i;
+                insert_cast_after(program, node, PrimitiveType::F32);
+                warn_at(
+                    program,
+                    diagnostics,
+                    node,
+                    format!("f64 input {} is narrowed to f32", program.input_label(i)),
+                    "This read",
+                );
+            }
+            Op::ReadProperty(p)
+                if is_f64(program.properties.get(*p).map(|x| x.primitive))
+                    && !only_feeds_cast(program, node, PrimitiveType::F32) =>
+            {
+                let p = *p;
+                insert_cast_after(program, node, PrimitiveType::F32);
+                warn_at(
+                    program,
+                    diagnostics,
+                    node,
+                    format!(
+                        "f64 property {} is narrowed to f32",
+                        program.property_label(p)
+                    ),
+                    "This read",
+                );
+            }
+            Op::ReadInputIndexed(b)
+                if is_f64(program.buffer_inputs.get(*b).map(|x| x.vector.primitive))
+                    && !only_feeds_cast(program, node, PrimitiveType::F32) =>
+            {
+                let b = *b;
+                insert_cast_after(program, node, PrimitiveType::F32);
+                warn_at(
+                    program,
+                    diagnostics,
+                    node,
+                    format!("f64 buffer input {} is narrowed to f32", b),
+                    "This read",
+                );
+            }
+            Op::WriteOutput(o)
+                if is_f64(program.outputs.get(*o).map(|x| x.primitive))
+                    && !only_fed_by_cast(program, node, PrimitiveType::F64) =>
+            {
+                // Widening back out loses nothing, so no warning here.
+                insert_cast_before(program, node, PrimitiveType::F64);
+            }
+            _ => {}
+        }
+    }
+
+    for s in program.states.iter_mut() {
+        if s.vector.primitive == PrimitiveType::F64 {
+            s.vector.primitive = PrimitiveType::F32;
+            if let Some(initial) = s.initial.as_mut() {
+                if let Ok(narrowed) = initial.fold_cast(PrimitiveType::F32) {
+                    *initial = narrowed;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legalizing_f64() {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F64, 2).unwrap();
+        let prop = prog
+            .add_property(PropertyDescriptor::new("prop", PrimitiveType::F64))
+            .unwrap();
+        let output = prog.add_output(PrimitiveType::F64, 2).unwrap();
+
+        let read_input = prog.op_read_input_node(input, None).unwrap();
+        let read_prop = prog.op_read_property_node(prop, None).unwrap();
+        // 0.1 isn't exactly representable, so this should warn.
+        let constant = prog
+            .op_constant_node(Constant::F64(vec![0.1, 0.5]), None)
+            .unwrap();
+        let add1 = prog.op_add_node(None).unwrap();
+        let add2 = prog.op_add_node(None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+
+        prog.connect(read_input, add1, 0, None).unwrap();
+        prog.connect(read_prop, add1, 1, None).unwrap();
+        prog.connect(add1, add2, 0, None).unwrap();
+        prog.connect(constant, add2, 1, None).unwrap();
+        prog.connect(add2, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        legalize_to_f32(&mut prog, &mut diags);
+        assert!(!diags.has_errors(), "{}", diags);
+        assert_eq!(diags.iter_level(DiagnosticLevel::Warning).count(), 3);
+
+        let types = type_inference(&prog, &mut diags).unwrap();
+        assert!(!diags.has_errors(), "{}", diags);
+        assert_eq!(types.get_type(read_input), Some(DataType::new_v_f64(2)));
+        assert_eq!(
+            types.get_type(read_prop),
+            Some(DataType::new_v_f64(1).with_rate(Rate::Control))
+        );
+        assert_eq!(
+            types.get_type(constant),
+            Some(DataType::new_v_f32(2).with_rate(Rate::Control))
+        );
+        assert_eq!(types.get_type(add1), Some(DataType::new_v_f32(2)));
+        assert_eq!(types.get_type(add2), Some(DataType::new_v_f32(2)));
+        assert_eq!(types.get_type(write), Some(DataType::new_v_f64(2)));
+    }
+
+    #[test]
+    fn test_legalizing_twice() {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F64, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F64, 1).unwrap();
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let negate = prog.op_negate_node(None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(read, negate, 0, None).unwrap();
+        prog.connect(negate, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        legalize_to_f32(&mut prog, &mut diags);
+        assert_eq!(diags.iter_level(DiagnosticLevel::Warning).count(), 1);
+
+        let mut diags = DiagnosticCollection::new();
+        assert_pass_makes_no_change(&prog, |p| legalize_to_f32(p, &mut diags));
+        assert!(diags.diagnostics.is_empty(), "{}", diags);
+
+        let types = type_inference(&prog, &mut diags).unwrap();
+        assert_eq!(types.get_type(negate), Some(DataType::new_v_f32(1)));
+        assert_eq!(types.get_type(write), Some(DataType::new_v_f64(1)));
+    }
+
+    #[test]
+    fn test_cast_warnings() {
+        for (prim, warnings) in [(PrimitiveType::F32, 0), (PrimitiveType::I64, 1)] {
+            let mut prog = Program::new();
+            let input = prog.add_input(prim, 1).unwrap();
+            let read = prog.op_read_input_node(input, None).unwrap();
+            let cast = prog.op_cast_node(PrimitiveType::F64, None).unwrap();
+            let negate = prog.op_negate_node(None).unwrap();
+            prog.connect(read, cast, 0, None).unwrap();
+            prog.connect(cast, negate, 0, None).unwrap();
+
+            let mut diags = DiagnosticCollection::new();
+            legalize_to_f32(&mut prog, &mut diags);
+            assert_eq!(prog.graph[cast].op, Op::Cast(PrimitiveType::F32));
+            assert_eq!(
+                diags.iter_level(DiagnosticLevel::Warning).count(),
+                warnings,
+                "{}",
+                diags
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_programs_are_left_alone() {
+        // Writing to an input which doesn't exist.
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F64, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F64, 1).unwrap();
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(read, write, 1, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        legalize_to_f32(&mut prog, &mut diags);
+        assert!(prog
+            .graph
+            .edges_directed(write, Direction::Incoming)
+            .all(|e| e.weight().input == 1));
+
+        // Reading an input which doesn't exist.
+        let mut prog = Program::new();
+        prog.op_read_input_node(0, None).unwrap();
+        let node_count = prog.graph.node_count();
+        legalize_for_target(&mut prog, &TargetSpec::f32_only(), &mut diags).unwrap();
+        assert_eq!(prog.graph.node_count(), node_count);
+    }
+
+    #[test]
+    fn test_f32_programs_are_untouched() {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F32, 1).unwrap();
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(read, write, 0, None).unwrap();
+
+        let node_count = prog.graph.node_count();
+        let mut diags = DiagnosticCollection::new();
+        legalize_to_f32(&mut prog, &mut diags);
+        assert_eq!(prog.graph.node_count(), node_count);
+        assert!(diags.diagnostics.is_empty(), "{}", diags);
+    }
+}
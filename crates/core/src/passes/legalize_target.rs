@@ -0,0 +1,191 @@
+//! Legalize a program for a given [TargetSpec].
+//!
+//! This checks the parts of a program which are fixed by its declarations against what the target supports, and
+//! rewrites what can be rewritten.  Currently the only rewrite is narrowing f64 to f32 for targets without f64.
+//!
+//! The types of the nodes themselves are only known after type inference, so [type_inference_for_target] checks those.
+use crate::*;
+
+#[derive(thiserror::Error, Debug)]
+#[error(
+    "Legalization for the target failed. Diagnostics have been pushed to the DiagnosticCollection"
+)]
+pub struct LegalizationError;
+
+/// Legalize a program for the given target.
+///
+/// If this pass fails, it has pushed the appropriate diagnostics already.  Type inference must run again after this
+/// pass, since it may rewrite nodes.
+pub fn legalize_for_target(
+    program: &mut Program,
+    target: &TargetSpec,
+    diagnostics: &mut DiagnosticCollection,
+) -> Result<(), LegalizationError> {
+    // f64 math can be done as f32 instead, so long as the target has f32.
+    let narrow_f64 = !target.supports_primitive(PrimitiveType::F64)
+        && target.supports_primitive(PrimitiveType::F32);
+    let legal = |prim: PrimitiveType| {
+        target.supports_primitive(prim) || (narrow_f64 && prim == PrimitiveType::F64)
+    };
+
+    let mut validation_succeeded = true;
+    let mut check = |what: &str, label: String, prim: PrimitiveType| {
+        if !legal(prim) {
+            diagnostics.add_simple_diagnostic(
+                program,
+                format!(
+                    "{} {} is of type {}, which the target does not support",
+                    what, label, prim
+                ),
+                None,
+            );
+            validation_succeeded = false;
+        }
+    };
+
+    for (i, vd) in program.inputs.iter().enumerate() {
+        check("Input", program.input_label(i), vd.primitive);
+    }
+
+    for (i, vd) in program.outputs.iter().enumerate() {
+        check("Output", program.output_label(i), vd.primitive);
+    }
+
+    for (i, prop) in program.iter_properties() {
+        check("Property", program.property_label(i), prop.primitive);
+    }
+
+    for (i, b) in program.buffer_inputs.iter().enumerate() {
+        check("Buffer input", i.to_string(), b.vector.primitive);
+    }
+
+    for (i, s) in program.states.iter().enumerate() {
+        check("State", i.to_string(), s.vector.primitive);
+    }
+
+    if !validation_succeeded {
+        return Err(LegalizationError);
+    }
+
+    if narrow_f64 {
+        legalize_to_f32(program, diagnostics);
+    }
+
+    // Check these after narrowing, since that may shrink states.
+    for e in target.state_limits.check(&program.states) {
+        diagnostics.add_simple_diagnostic(program, e.to_string(), None);
+        validation_succeeded = false;
+    }
+
+    if !validation_succeeded {
+        return Err(LegalizationError);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f32_only_target_narrows() {
+        let mut prog = Program::new();
+        let output = prog.add_output(PrimitiveType::F64, 1).unwrap();
+        let constant = prog
+            .op_constant_node(Constant::F64(vec![1.0]), None)
+            .unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(constant, write, 0, None).unwrap();
+        prog.states.push(State {
+            vector: VectorDescriptor::new_f64(2),
+            length: 10,
+            initial: Some(Constant::F64(vec![0.5])),
+        });
+
+        let mut diags = DiagnosticCollection::new();
+        legalize_for_target(&mut prog, &TargetSpec::f32_only(), &mut diags).unwrap();
+        assert!(!diags.has_errors(), "{}", diags);
+        assert_eq!(
+            prog.graph.node_weight(constant).unwrap().op,
+            Op::Constant(Constant::F32(vec![1.0]))
+        );
+        assert_eq!(prog.states[0].vector, VectorDescriptor::new_f32(2));
+        assert_eq!(prog.states[0].initial, Some(Constant::F32(vec![0.5])));
+    }
+
+    #[test]
+    fn test_unsupported_primitives() {
+        let mut prog = Program::new();
+        prog.add_input(PrimitiveType::I64, 1).unwrap();
+
+        let target = TargetSpec {
+            supported_primitives: std::borrow::Cow::Borrowed(&[PrimitiveType::F32]),
+            ..Default::default()
+        };
+        let mut diags = DiagnosticCollection::new();
+        assert!(legalize_for_target(&mut prog, &target, &mut diags).is_err());
+        assert!(diags.has_errors());
+    }
+
+    fn limited(max_state_bytes: Option<u64>, max_total_state_bytes: Option<u64>) -> TargetSpec {
+        TargetSpec {
+            state_limits: StateLimits {
+                max_state_bytes,
+                max_total_state_bytes,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_max_total_state_bytes() {
+        let mut prog = Program::new();
+        prog.states.push(State {
+            vector: VectorDescriptor::new_f32(2),
+            length: 100,
+            initial: None,
+        });
+
+        let mut diags = DiagnosticCollection::new();
+        legalize_for_target(&mut prog, &limited(None, Some(800)), &mut diags).unwrap();
+        assert!(legalize_for_target(&mut prog, &limited(None, Some(799)), &mut diags).is_err());
+
+        // Absurdly large states must be rejected, not overflow.
+        prog.states.push(State {
+            vector: VectorDescriptor::new_f64(u64::MAX),
+            length: u64::MAX,
+            initial: None,
+        });
+        let target = limited(None, Some(u64::MAX - 1));
+        assert_eq!(
+            target.state_limits.check(&prog.states),
+            vec![StateLimitError::TotalTooLarge {
+                state: 1,
+                bytes: u64::MAX,
+                limit: u64::MAX - 1
+            }]
+        );
+        assert!(legalize_for_target(&mut prog, &target, &mut diags).is_err());
+    }
+
+    #[test]
+    fn test_max_state_bytes() {
+        let mut prog = Program::new();
+        prog.add_state(PrimitiveType::F32, 1, 10).unwrap();
+        prog.states.push(State {
+            vector: VectorDescriptor::new_f64(u64::MAX),
+            length: u64::MAX,
+            initial: None,
+        });
+
+        let target = limited(Some(1 << 30), None);
+        let mut diags = DiagnosticCollection::new();
+        assert!(legalize_for_target(&mut prog, &target, &mut diags).is_err());
+        assert_eq!(diags.iter_level(DiagnosticLevel::Error).count(), 1);
+
+        prog.states.pop();
+        let mut diags = DiagnosticCollection::new();
+        legalize_for_target(&mut prog, &target, &mut diags).unwrap();
+    }
+}
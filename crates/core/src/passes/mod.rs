@@ -1,3 +1,14 @@
+mod dead_code_elimination;
 mod insert_start_final_edges;
+mod legalize_f32;
+mod legalize_target;
 mod type_inference;
 mod unify_vectors;
+mod validate_feedback;
+
+pub use dead_code_elimination::*;
+pub use insert_start_final_edges::*;
+pub use legalize_f32::*;
+pub use legalize_target::*;
+pub use type_inference::*;
+pub use validate_feedback::*;
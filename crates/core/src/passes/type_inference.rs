@@ -4,6 +4,11 @@
 //! produce an output type for the node.  The output of this pass is the output types of all nodes, from which the types
 //! of edges can trivially be inferred.
 //!
+//! Types also carry a [Rate].  Inputs, clocks and states are audio rate, while properties, constants and the sample
+//! rate are control rate.  Nodes take the fastest rate of their inputs, so whole subgraphs which depend only on
+//! control-rate values are control rate and may be computed once per block.  Control-rate values may be written to
+//! outputs and states, which are audio rate; that is where they are upsampled, by holding them for the block.
+//!
 //! This pass must run before insertion of the implicit add nodes and so must deal with the case of multiple incoming
 //! edges per input.  This is because type inference is one of the final places in which good diagnostics must be
 //! generated: if type inference succeeds, the program is valid and any bugs that make it invalid are on us, not the
@@ -43,11 +48,38 @@ enum TypeConstraint {
     IsFromOutput(usize),
     IsFromProperty(usize),
 
+    /// The type is a frame of the given buffer input.  The single input is the index, which must be a scalar and not any
+    /// of the listed primitives.
+    IsFromBufferInput {
+        buffer: usize,
+        denied: &'static [PrimitiveType],
+    },
+
+    /// The type is that of the given state.  If the node has inputs, they must also be of that type.
+    IsFromState(usize),
+
     /// The node outputs this primitive, but the width must be inferred.
     IsPrimitive(PrimitiveType),
+
+    /// Like [TypeConstraint::IsPrimitive], but the inputs must not be any of the listed primitives.
+    IsPrimitiveFrom {
+        primitive: PrimitiveType,
+        denied: &'static [PrimitiveType],
+    },
     /// The type of this node is inferred from the inputs, but must not be one of the listed primitives, or never.
     MustNotBePrimitive(&'static [PrimitiveType]),
 
+    /// The inputs are compared, and must unify and not be any of the listed primitives.  The output is a bool of the
+    /// unified width.
+    Compare(&'static [PrimitiveType]),
+
+    /// The first input is a bool condition, and the other two are the values to select between.
+    Select,
+
+    /// The first input is a float stereo pair, a vector of width 2, and the output is of the same type.  Any further
+    /// inputs are parameters, which must be scalars of the same primitive.
+    Stereo,
+
     /// Infer the type from the node inputs; anything but Never is fine.
     FromNodeInputs,
 }
@@ -75,13 +107,20 @@ fn descriptor_for_op(op: &Op) -> OpDescriptor {
                 cares_about_inputs: false,
             },
         },
-        Op::Clock | Op::Sr => OpDescriptor {
+        Op::Clock => OpDescriptor {
             num_inputs: 0,
             constraint: TypeConstraint::IsExactly {
                 data_type: DataType::Vector(VectorDescriptor::new_i64(1)),
                 cares_about_inputs: true,
             },
         },
+        Op::Sr => OpDescriptor {
+            num_inputs: 0,
+            constraint: TypeConstraint::IsExactly {
+                data_type: DataType::Vector(VectorDescriptor::new_i64(1).with_rate(Rate::Control)),
+                cares_about_inputs: true,
+            },
+        },
         Op::Constant(c) => OpDescriptor {
             num_inputs: 0,
             constraint: TypeConstraint::IsExactly {
@@ -93,13 +132,63 @@ fn descriptor_for_op(op: &Op) -> OpDescriptor {
             num_inputs: 1,
             constraint: TypeConstraint::IsPrimitive(*prim),
         },
+        Op::FloatToInt(_) => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::IsPrimitiveFrom {
+                primitive: PrimitiveType::I64,
+                denied: &[PrimitiveType::Bool, PrimitiveType::I64],
+            },
+        },
         Op::Negate => OpDescriptor {
             num_inputs: 1,
             constraint: TypeConstraint::MustNotBePrimitive(&[PrimitiveType::Bool]),
         },
-        Op::BinOp(_) => OpDescriptor {
+        Op::LogicalNot => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::MustNotBePrimitive(&[
+                PrimitiveType::I64,
+                PrimitiveType::F32,
+                PrimitiveType::F64,
+            ]),
+        },
+        Op::BitNot => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::MustNotBePrimitive(&[
+                PrimitiveType::Bool,
+                PrimitiveType::F32,
+                PrimitiveType::F64,
+            ]),
+        },
+        Op::UnaryOp(o) => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::MustNotBePrimitive(o.denied_primitives()),
+        },
+        Op::Compare(o) => OpDescriptor {
             num_inputs: 2,
-            constraint: TypeConstraint::MustNotBePrimitive(&[PrimitiveType::Bool]),
+            constraint: TypeConstraint::Compare(o.denied_primitives()),
+        },
+        Op::Lerp => OpDescriptor {
+            num_inputs: 3,
+            constraint: TypeConstraint::MustNotBePrimitive(&[
+                PrimitiveType::Bool,
+                PrimitiveType::I64,
+            ]),
+        },
+        Op::MsEncode | Op::MsDecode => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::Stereo,
+        },
+        Op::StereoWidth => OpDescriptor {
+            num_inputs: 2,
+            constraint: TypeConstraint::Stereo,
+        },
+        Op::Select => OpDescriptor {
+            num_inputs: 3,
+            constraint: TypeConstraint::Select,
+        },
+        Op::BinOp(o) => OpDescriptor {
+            num_inputs: 2,
+            constraint: TypeConstraint::MustNotBePrimitive(o.denied_primitives()),
         },
         Op::ReadInput(i) => OpDescriptor {
             num_inputs: 0,
@@ -109,16 +198,151 @@ fn descriptor_for_op(op: &Op) -> OpDescriptor {
             num_inputs: 0,
             constraint: TypeConstraint::IsFromProperty(*p),
         },
+        Op::ReadInputIndexed(b) => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::IsFromBufferInput {
+                buffer: *b,
+                denied: INDEX_DENIED_PRIMITIVES,
+            },
+        },
         Op::WriteOutput(o) => OpDescriptor {
             num_inputs: 1,
             constraint: TypeConstraint::IsFromOutput(*o),
         },
+        Op::FeedbackRead(s) | Op::Noise(s) => OpDescriptor {
+            num_inputs: 0,
+            constraint: TypeConstraint::IsFromState(*s),
+        },
+        Op::FeedbackWrite(s) | Op::Phasor(s) => OpDescriptor {
+            num_inputs: 1,
+            constraint: TypeConstraint::IsFromState(*s),
+        },
+        Op::SinOsc(s) => OpDescriptor {
+            num_inputs: 2,
+            constraint: TypeConstraint::IsFromState(*s),
+        },
     }
 }
 
+/// Why unifying the inputs of a node failed.
+enum UnifyFailure {
+    /// An input has no type, because checking it already failed.
+    Untyped,
+
+    Diagnostic(Diagnostic),
+}
+
+/// Unify the types of some inputs of a node.
+///
+/// Inputs of type [DataType::Never] are skipped, and if all inputs are skipped this returns `Ok(None)`.
+fn unify_inputs<'a>(
+    program: &Program,
+    type_info: &TypeInfo,
+    node: OperationGraphNode,
+    inputs: impl Iterator<Item = &'a MaterializedInput>,
+    disallowed: Option<&'static [PrimitiveType]>,
+) -> Result<Option<VectorDescriptor>, UnifyFailure> {
+    let mut unifier = None;
+
+    for i in inputs {
+        let ty = type_info
+            .get_type(i.source_node)
+            .ok_or(UnifyFailure::Untyped)?;
+
+        let vd = match ty {
+            DataType::Vector(x) => x,
+            DataType::Never => {
+                // Skip this. We are doing unification early, so this can come up.
+                continue;
+            }
+        };
+
+        if unifier.is_none() {
+            unifier = Some(
+                crate::passes::unify_vectors::VectorUnifier::new(program, node, vd, disallowed)
+                    .map_err(UnifyFailure::Diagnostic)?,
+            );
+        }
+        let u = unifier
+            .as_mut()
+            .expect("We just initialized the unifier if needed");
+
+        u.present(program, node, vd)
+            .map_err(UnifyFailure::Diagnostic)?;
+    }
+
+    unifier
+        .map(|u| u.resolve(program))
+        .transpose()
+        .map_err(UnifyFailure::Diagnostic)
+}
+
+/// Does this op read or write a declaration, at the boundary with the host?
+fn is_boundary_op(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::ReadInput(_) | Op::ReadProperty(_) | Op::ReadInputIndexed(_) | Op::WriteOutput(_)
+    )
+}
+
+/// Find a primitive which node `n` uses, but the target doesn't support.
+///
+/// The types of boundary nodes come from declarations, which [legalize_for_target] checks instead.  The sides of casts
+/// which only touch boundary nodes are exempt too, since that is how f64 declarations are narrowed for f32 targets.
+fn find_unsupported_primitive(
+    program: &Program,
+    target: &TargetSpec,
+    n: OperationGraphNode,
+    output: Option<PrimitiveType>,
+    input: Option<PrimitiveType>,
+) -> Option<PrimitiveType> {
+    use petgraph::prelude::*;
+
+    let op = &program.graph[n].op;
+    if is_boundary_op(op) {
+        return None;
+    }
+
+    let only_touches_boundary = |dir: Direction| {
+        let mut neighbors = program
+            .graph
+            .neighbors_directed(n, dir)
+            .filter(|x| *x != program.start_node && *x != program.final_node)
+            .peekable();
+        neighbors.peek().is_some() && neighbors.all(|x| is_boundary_op(&program.graph[x].op))
+    };
+
+    let (output, input) = if op.is_cast() {
+        (
+            output.filter(|_| !only_touches_boundary(Direction::Outgoing)),
+            input.filter(|_| !only_touches_boundary(Direction::Incoming)),
+        )
+    } else {
+        (output, input)
+    };
+
+    [output, input]
+        .into_iter()
+        .flatten()
+        .find(|prim| !target.supports_primitive(*prim))
+}
+
+/// Infer the types of all nodes, with no target-specific restrictions.
 pub fn type_inference(
     program: &Program,
     diagnostics: &mut DiagnosticCollection,
+) -> Result<TypeInfo, TypeInferenceError> {
+    type_inference_for_target(program, &TargetSpec::default(), diagnostics)
+}
+
+/// Infer the types of all nodes, also rejecting nodes which use primitives the target doesn't support, or denies for
+/// their op.
+///
+/// See [TargetSpec::supported_primitives] and [TargetSpec::denied_op_primitives].
+pub fn type_inference_for_target(
+    program: &Program,
+    target: &TargetSpec,
+    diagnostics: &mut DiagnosticCollection,
 ) -> Result<TypeInfo, TypeInferenceError> {
     let mut type_info = TypeInfo {
         types: Default::default(),
@@ -158,9 +382,8 @@ pub fn type_inference(
             continue;
         }
 
-        // We don't want the start node; that is never involved in type inference.
-        let inputs =
-            MaterializedInputs::materialize_with_filter(program, n, |x| x != program.start_node);
+        // We don't want the start node or ordering edges; those are never involved in type inference.
+        let inputs = MaterializedInputs::materialize_data_inputs(program, n);
 
         if descriptor.num_inputs < inputs.inputs.len() {
             diagnostics.add_simple_diagnostic(
@@ -202,66 +425,88 @@ pub fn type_inference(
             }
         }
 
-        // For now we have only nodes which have inputs all of the same type, and which we can treat as collapsed into
-        // one input. Infer the type, so we can uise it below.
-        let all_inputs = inputs.inputs.iter().flat_map(|x| x.iter()).cloned();
-        let mut unifier = None;
-        for i in all_inputs {
-            let ty = match type_info.get_type(i.source_node) {
-                Some(t) => t,
-                None => {
-                    uncheckable_count += 1;
-                    continue 'check_next;
-                }
-            };
+        let unified_ty = if let TypeConstraint::Select = descriptor.constraint {
+            // The condition and the values are unified separately, then the condition must broadcast with the values.
+            let cond = unify_inputs(
+                program,
+                &type_info,
+                n,
+                inputs.get_input(0).iter(),
+                Some(&[PrimitiveType::I64, PrimitiveType::F32, PrimitiveType::F64]),
+            );
+            let values = unify_inputs(
+                program,
+                &type_info,
+                n,
+                inputs.get_input(1).iter().chain(inputs.get_input(2).iter()),
+                None,
+            );
 
-            let vd = match ty {
-                DataType::Vector(x) => x,
-                DataType::Never => {
-                    // Skip this. We are doing unification early, so this can come up.
+            match (cond, values) {
+                (Err(UnifyFailure::Diagnostic(d)), _) | (_, Err(UnifyFailure::Diagnostic(d))) => {
+                    diagnostics.add_diagnostic(d);
                     continue;
                 }
-            };
-
-            if unifier.is_none() {
-                let disallowed =
-                    if let TypeConstraint::MustNotBePrimitive(forbidden) = &descriptor.constraint {
-                        Some(*forbidden)
-                    } else {
-                        None
-                    };
-                unifier = match crate::passes::unify_vectors::VectorUnifier::new(
-                    program, n, vd, disallowed,
-                ) {
-                    Ok(u) => Some(u),
-                    Err(d) => {
-                        diagnostics.add_diagnostic(d);
-                        continue 'check_next;
-                    }
+                (Err(UnifyFailure::Untyped), _) | (_, Err(UnifyFailure::Untyped)) => {
+                    uncheckable_count += 1;
+                    continue;
                 }
-            }
-            let u = unifier
-                .as_mut()
-                .expect("We just initialized the unifier if needed");
+                (Ok(Some(c)), Ok(Some(v))) => {
+                    if c.width != 1 && v.width != 1 && c.width != v.width {
+                        diagnostics.add_simple_diagnostic(
+                            program,
+                            format!(
+                                "Select: unable to broadcast condition of type {} with values of type {}",
+                                c, v
+                            ),
+                            kind.source_loc.clone(),
+                        );
+                        continue;
+                    }
 
-            match u.present(program, n, vd) {
-                Ok(()) => {}
-                Err(d) => {
-                    diagnostics.add_diagnostic(d);
-                    continue 'check_next;
+                    Some(
+                        VectorDescriptor::new(v.primitive, c.width.max(v.width))
+                            .with_rate(c.rate.max(v.rate)),
+                    )
+                }
+                _ => {
+                    diagnostics.add_simple_diagnostic(
+                        program,
+                        "Select: inputs must carry data",
+                        kind.source_loc.clone(),
+                    );
+                    continue;
                 }
             }
-        }
+        } else {
+            // Other nodes have inputs all of the same type, which we can treat as collapsed into one input. Infer the
+            // type, so we can use it below.
+            let disallowed = match &descriptor.constraint {
+                TypeConstraint::MustNotBePrimitive(forbidden) => Some(*forbidden),
+                TypeConstraint::Compare(forbidden) => Some(*forbidden),
+                TypeConstraint::Stereo => Some(&[PrimitiveType::Bool, PrimitiveType::I64][..]),
+                TypeConstraint::IsPrimitiveFrom { denied, .. }
+                | TypeConstraint::IsFromBufferInput { denied, .. } => Some(*denied),
+                _ => None,
+            };
 
-        let unified_ty = match unifier {
-            Some(u) => match u.resolve(program) {
-                Ok(x) => Some(x),
-                Err(d) => {
+            match unify_inputs(
+                program,
+                &type_info,
+                n,
+                inputs.inputs.iter().flat_map(|x| x.iter()),
+                disallowed,
+            ) {
+                Ok(x) => x,
+                Err(UnifyFailure::Untyped) => {
+                    uncheckable_count += 1;
+                    continue;
+                }
+                Err(UnifyFailure::Diagnostic(d)) => {
                     diagnostics.add_diagnostic(d);
                     continue;
                 }
-            },
-            None => None,
+            }
         };
 
         let ty = match descriptor.constraint {
@@ -282,7 +527,9 @@ pub fn type_inference(
                 }
             },
             TypeConstraint::IsFromProperty(i) => match program.properties.get(i) {
-                Some(x) => DataType::Vector(VectorDescriptor::new(*x, 1)),
+                Some(x) => {
+                    DataType::Vector(VectorDescriptor::new(x.primitive, 1).with_rate(Rate::Control))
+                }
                 None => {
                     diagnostics.add_simple_diagnostic(
                         program,
@@ -296,9 +543,42 @@ pub fn type_inference(
                     continue;
                 }
             },
+            TypeConstraint::IsFromBufferInput { buffer: b, .. } => {
+                let buffer = match program.buffer_inputs.get(b) {
+                    Some(x) => x,
+                    None => {
+                        diagnostics.add_simple_diagnostic(
+                            program,
+                            format!(
+                                "Attempt to read buffer input {}, but only {} buffer inputs available",
+                                b,
+                                program.buffer_inputs.len()
+                            ),
+                            kind.source_loc.clone(),
+                        );
+                        continue;
+                    }
+                };
+
+                let index = unified_ty.expect("Indexed reads have 1 input");
+                if index.width != 1 {
+                    diagnostics.add_simple_diagnostic(
+                        program,
+                        format!(
+                            "{}: the index must be a scalar, but found {}",
+                            kind.op, index
+                        ),
+                        kind.source_loc.clone(),
+                    );
+                    continue;
+                }
+
+                // The buffer only changes between blocks, so the read is as fast as the index.
+                DataType::Vector(buffer.vector.with_rate(index.rate))
+            }
             TypeConstraint::IsFromOutput(o) => {
                 let expected = match program.outputs.get(o) {
-                    Some(x) => DataType::Vector(*x),
+                    Some(x) => *x,
                     None => {
                         diagnostics.add_simple_diagnostic(
                             program,
@@ -314,25 +594,60 @@ pub fn type_inference(
                 };
 
                 let has = unified_ty.expect("Output nodes have at least 1 input, so we will fail early if no unification is possible");
-                if expected != DataType::Vector(has) {
+                if !expected.accepts(&has) {
                     diagnostics.add_simple_diagnostic(
                         program,
                         format!(
                             "Attempt to write output {}: expected {} but found {}",
-                            o, expected, has
+                            program.output_label(o),
+                            expected,
+                            has
                         ),
                         kind.source_loc.clone(),
                     );
                     continue;
                 }
 
-                expected
+                DataType::Vector(expected)
             }
-            TypeConstraint::IsPrimitive(prim) => {
+            TypeConstraint::IsFromState(s) => {
+                let expected = match program.states.get(s) {
+                    Some(x) => x.vector,
+                    None => {
+                        diagnostics.add_simple_diagnostic(
+                            program,
+                            format!(
+                                "Attempt to use state {}, but only {} states available",
+                                s,
+                                program.states.len()
+                            ),
+                            kind.source_loc.clone(),
+                        );
+                        continue;
+                    }
+                };
+
+                if let Some(has) = unified_ty {
+                    if !expected.accepts(&has) {
+                        diagnostics.add_simple_diagnostic(
+                            program,
+                            format!("{}: state {} is {} but found {}", kind.op, s, expected, has),
+                            kind.source_loc.clone(),
+                        );
+                        continue;
+                    }
+                }
+
+                DataType::Vector(expected)
+            }
+            TypeConstraint::IsPrimitive(prim)
+            | TypeConstraint::IsPrimitiveFrom {
+                primitive: prim, ..
+            } => {
                 let got =
                     unified_ty.expect("Any nodes which must be a primitive have at least 1 input");
 
-                DataType::new_vector(prim, got.width)
+                DataType::Vector(VectorDescriptor::new(prim, got.width).with_rate(got.rate))
             }
             TypeConstraint::MustNotBePrimitive(prims) => {
                 let got = unified_ty
@@ -358,11 +673,83 @@ pub fn type_inference(
 
                 DataType::Vector(got)
             }
+            TypeConstraint::Compare(_) => {
+                let got = unified_ty.expect("Comparisons have 2 inputs");
+                DataType::Vector(VectorDescriptor::new_bool(got.width).with_rate(got.rate))
+            }
+            TypeConstraint::Select => DataType::Vector(unified_ty.expect("Select has 3 inputs")),
+            TypeConstraint::Stereo => {
+                let got = unified_ty.expect("Stereo ops have at least 1 input");
+                if got.width != 2 {
+                    diagnostics.add_simple_diagnostic(
+                        program,
+                        format!(
+                            "{}: expected a stereo pair of width 2, but found {}",
+                            kind.op, got
+                        ),
+                        kind.source_loc.clone(),
+                    );
+                    continue;
+                }
+
+                let wide_parameter = inputs.inputs.iter().skip(1).flatten().any(|i| {
+                    type_info
+                        .get_type(i.source_node)
+                        .and_then(|t| t.as_vector().map(|v| v.width))
+                        != Some(1)
+                });
+                if wide_parameter {
+                    diagnostics.add_simple_diagnostic(
+                        program,
+                        format!("{}: parameters must be scalars", kind.op),
+                        kind.source_loc.clone(),
+                    );
+                    continue;
+                }
+
+                DataType::Vector(got)
+            }
             TypeConstraint::FromNodeInputs => {
                 DataType::Vector(unified_ty.expect("This node type has at least 1 input"))
             }
         };
 
+        let denied = [
+            ty.as_vector().map(|v| v.primitive),
+            unified_ty.map(|v| v.primitive),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|prim| target.denies_op_primitive(&kind.op, *prim));
+        if let Some(prim) = denied {
+            let mut builder = DiagnosticBuilder::new(
+                format!("{} on {} is not supported by the target", kind.op, prim),
+                None,
+            );
+            builder.node_ref("This node", n);
+            diagnostics.add_diagnostic(builder.build(program));
+            continue;
+        }
+
+        if let Some(prim) = find_unsupported_primitive(
+            program,
+            target,
+            n,
+            ty.as_vector().map(|v| v.primitive),
+            unified_ty.map(|v| v.primitive),
+        ) {
+            let mut builder = DiagnosticBuilder::new(
+                format!(
+                    "{} uses {}, which the target does not support",
+                    kind.op, prim
+                ),
+                None,
+            );
+            builder.node_ref("This node", n);
+            diagnostics.add_diagnostic(builder.build(program));
+            continue;
+        }
+
         type_info.types.insert(n, ty);
         successes += 1;
     }
@@ -406,8 +793,12 @@ mod tests {
         let i_i64_v1 = prog.add_input(PrimitiveType::I64, 1).unwrap();
         let i_f32_v2 = prog.add_input(PrimitiveType::F32, 2).unwrap();
 
-        let p_i64_v1 = prog.add_property(PrimitiveType::I64).unwrap();
-        let p_f32_v1 = prog.add_property(PrimitiveType::F32).unwrap();
+        let p_i64_v1 = prog
+            .add_property(PropertyDescriptor::new("p_i64", PrimitiveType::I64))
+            .unwrap();
+        let p_f32_v1 = prog
+            .add_property(PropertyDescriptor::new("p_f32", PrimitiveType::F32))
+            .unwrap();
 
         let o_i64_v2 = prog.add_output(PrimitiveType::I64, 2).unwrap();
         let o_f64_v2 = prog.add_output(PrimitiveType::F64, 2).unwrap();
@@ -466,13 +857,16 @@ mod tests {
         );
         assert_eq!(
             typed.get_type(read_prop_i64_v1),
-            Some(DataType::new_v_i64(1))
+            Some(DataType::new_v_i64(1).with_rate(Rate::Control))
         );
         assert_eq!(
             typed.get_type(read_prop_f32_v1),
-            Some(DataType::new_v_f32(1))
+            Some(DataType::new_v_f32(1).with_rate(Rate::Control))
+        );
+        assert_eq!(
+            typed.get_type(const_f64_v1),
+            Some(DataType::new_v_f64(1).with_rate(Rate::Control))
         );
-        assert_eq!(typed.get_type(const_f64_v1), Some(DataType::new_v_f64(1)));
         assert_eq!(typed.get_type(cast_f64_v2), Some(DataType::new_v_f64(2)));
         assert_eq!(
             typed.get_type(broadcasted_add_f64_v2),
@@ -497,6 +891,73 @@ mod tests {
         assert!(res.is_err(), "{}\n", diags);
     }
 
+    #[test]
+    fn test_rates() {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let prop = prog
+            .add_property(PropertyDescriptor::new("gain", PrimitiveType::F32))
+            .unwrap();
+        let output = prog.add_output(PrimitiveType::F32, 1).unwrap();
+
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let gain = prog.op_read_property_node(prop, None).unwrap();
+        let squared = prog.op_mul_node(None).unwrap();
+        prog.connect(gain, squared, 0, None).unwrap();
+        prog.connect(gain, squared, 1, None).unwrap();
+        let scaled = prog.op_mul_node(None).unwrap();
+        prog.connect(read, scaled, 0, None).unwrap();
+        prog.connect(squared, scaled, 1, None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(scaled, write, 0, None).unwrap();
+
+        // A control-rate value may also be written directly, which upsamples it.
+        let output2 = prog.add_output(PrimitiveType::F32, 1).unwrap();
+        let write2 = prog.op_write_output_node(output2, None).unwrap();
+        prog.connect(squared, write2, 0, None).unwrap();
+
+        let typed = type_program(&mut prog);
+        let control = DataType::new_v_f32(1).with_rate(Rate::Control);
+        assert_eq!(typed.get_type(gain), Some(control));
+        assert_eq!(typed.get_type(squared), Some(control));
+        assert_eq!(typed.get_type(scaled), Some(DataType::new_v_f32(1)));
+        assert_eq!(typed.get_type(write), Some(DataType::new_v_f32(1)));
+        assert_eq!(typed.get_type(write2), Some(DataType::new_v_f32(1)));
+    }
+
+    #[test]
+    fn test_buffer_inputs() {
+        let mut prog = Program::new();
+        let table = prog.add_buffer_input(PrimitiveType::F32, 2, 2048).unwrap();
+
+        let clock = prog.op_clock_node(None).unwrap();
+        let read = prog.op_read_input_indexed_node(table, None).unwrap();
+        prog.connect(clock, read, 0, None).unwrap();
+
+        let index = prog
+            .op_constant_node(Constant::I64(vec![-1]), None)
+            .unwrap();
+        let last = prog.op_read_input_indexed_node(table, None).unwrap();
+        prog.connect(index, last, 0, None).unwrap();
+
+        let typed = type_program(&mut prog);
+        assert_eq!(typed.get_type(read), Some(DataType::new_v_f32(2)));
+        assert_eq!(
+            typed.get_type(last),
+            Some(DataType::new_v_f32(2).with_rate(Rate::Control))
+        );
+
+        // Indices must be scalar integers.
+        for index in [Constant::F32(vec![0.0]), Constant::I64(vec![0, 1])] {
+            let mut prog = Program::new();
+            let table = prog.add_buffer_input(PrimitiveType::F32, 1, 16).unwrap();
+            let index = prog.op_constant_node(index, None).unwrap();
+            let read = prog.op_read_input_indexed_node(table, None).unwrap();
+            prog.connect(index, read, 0, None).unwrap();
+            assert_fails_typing(&mut prog);
+        }
+    }
+
     #[test]
     fn test_too_few_inputs() {
         let mut prog = Program::new();
@@ -561,6 +1022,442 @@ mod tests {
         assert_fails_typing(&mut prog);
     }
 
+    #[test]
+    fn test_logical_ops() {
+        let mut prog = Program::new();
+        let o = prog.add_output(PrimitiveType::Bool, 2).unwrap();
+        let writer = prog.op_write_output_node(o, None).unwrap();
+        let c1 = prog
+            .op_constant_node(Constant::Bool(vec![true, false]), None)
+            .unwrap();
+        let c2 = prog
+            .op_constant_node(Constant::Bool(vec![true]), None)
+            .unwrap();
+        let and = prog.op_logical_and_node(None).unwrap();
+        let not = prog.op_logical_not_node(None).unwrap();
+        prog.connect(c1, and, 0, None).unwrap();
+        prog.connect(c2, and, 1, None).unwrap();
+        prog.connect(and, not, 0, None).unwrap();
+        prog.connect(not, writer, 0, None).unwrap();
+
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(and),
+            Some(DataType::new_v_bool(2).with_rate(Rate::Control))
+        );
+        assert_eq!(
+            typed.get_type(not),
+            Some(DataType::new_v_bool(2).with_rate(Rate::Control))
+        );
+    }
+
+    #[test]
+    fn test_logical_ops_reject_numbers() {
+        let mut prog = Program::new();
+        let c1 = prog
+            .op_constant_node(Constant::F32(vec![1.0]), None)
+            .unwrap();
+        let or = prog.op_logical_or_node(None).unwrap();
+        prog.connect(c1, or, 0, None).unwrap();
+        prog.connect(c1, or, 1, None).unwrap();
+        assert_fails_typing(&mut prog);
+
+        let mut prog = Program::new();
+        let c1 = prog.op_constant_node(Constant::I64(vec![1]), None).unwrap();
+        let not = prog.op_logical_not_node(None).unwrap();
+        prog.connect(c1, not, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_bit_ops_are_integer_only() {
+        let mut prog = Program::new();
+        let o = prog.add_output(PrimitiveType::I64, 1).unwrap();
+        let writer = prog.op_write_output_node(o, None).unwrap();
+        let c = prog.op_constant_node(Constant::I64(vec![3]), None).unwrap();
+        let shl = prog.op_shl_node(None).unwrap();
+        let not = prog.op_bit_not_node(None).unwrap();
+        prog.connect(c, shl, 0, None).unwrap();
+        prog.connect(c, shl, 1, None).unwrap();
+        prog.connect(shl, not, 0, None).unwrap();
+        prog.connect(not, writer, 0, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(not),
+            Some(DataType::new_v_i64(1).with_rate(Rate::Control))
+        );
+
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F32(vec![3.0]), None)
+            .unwrap();
+        let xor = prog.op_bit_xor_node(None).unwrap();
+        prog.connect(c, xor, 0, None).unwrap();
+        prog.connect(c, xor, 1, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_unary_math() {
+        let mut prog = Program::new();
+        let o = prog.add_output(PrimitiveType::F32, 2).unwrap();
+        let writer = prog.op_write_output_node(o, None).unwrap();
+        let c = prog
+            .op_constant_node(Constant::F32(vec![1.0, 2.0]), None)
+            .unwrap();
+        let abs = prog.op_abs_node(None).unwrap();
+        let sqrt = prog.op_sqrt_node(None).unwrap();
+        prog.connect(c, abs, 0, None).unwrap();
+        prog.connect(abs, sqrt, 0, None).unwrap();
+        prog.connect(sqrt, writer, 0, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(sqrt),
+            Some(DataType::new_v_f32(2).with_rate(Rate::Control))
+        );
+
+        // Abs works on integers, but the transcendental functions don't.
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::I64(vec![-1]), None)
+            .unwrap();
+        let abs = prog.op_abs_node(None).unwrap();
+        prog.connect(c, abs, 0, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(abs),
+            Some(DataType::new_v_i64(1).with_rate(Rate::Control))
+        );
+
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![1]), None).unwrap();
+        let log = prog.op_log_node(None).unwrap();
+        prog.connect(c, log, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_rounding_is_float_only() {
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F64(vec![1.5]), None)
+            .unwrap();
+        let fract = prog.op_fract_node(None).unwrap();
+        prog.connect(c, fract, 0, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(fract),
+            Some(DataType::new_v_f64(1).with_rate(Rate::Control))
+        );
+
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![1]), None).unwrap();
+        let floor = prog.op_floor_node(None).unwrap();
+        prog.connect(c, floor, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_float_to_int() {
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F32(vec![1.5, 2.5]), None)
+            .unwrap();
+        let conv = prog
+            .op_float_to_int_node(RoundingMode::NearestEven, None)
+            .unwrap();
+        prog.connect(c, conv, 0, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(conv),
+            Some(DataType::new_v_i64(2).with_rate(Rate::Control))
+        );
+
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![1]), None).unwrap();
+        let conv = prog
+            .op_float_to_int_node(RoundingMode::Trunc, None)
+            .unwrap();
+        prog.connect(c, conv, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_stereo() {
+        let mut prog = Program::new();
+        let i = prog.add_input(PrimitiveType::F32, 2).unwrap();
+        let read = prog.op_read_input_node(i, None).unwrap();
+        let width = prog
+            .op_constant_node(Constant::F32(vec![1.5]), None)
+            .unwrap();
+        let encode = prog.op_ms_encode_node(None).unwrap();
+        let decode = prog.op_ms_decode_node(None).unwrap();
+        let widen = prog.op_stereo_width_node(None).unwrap();
+        prog.connect(read, encode, 0, None).unwrap();
+        prog.connect(encode, decode, 0, None).unwrap();
+        prog.connect(decode, widen, 0, None).unwrap();
+        prog.connect(width, widen, 1, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(typed.get_type(encode), Some(DataType::new_v_f32(2)));
+        assert_eq!(typed.get_type(widen), Some(DataType::new_v_f32(2)));
+
+        // Only pairs are stereo.
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F32(vec![1.0, 2.0, 3.0]), None)
+            .unwrap();
+        let encode = prog.op_ms_encode_node(None).unwrap();
+        prog.connect(c, encode, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+
+        // The width is a scalar, not a width per channel.
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F32(vec![1.0, 2.0]), None)
+            .unwrap();
+        let widen = prog.op_stereo_width_node(None).unwrap();
+        prog.connect(c, widen, 0, None).unwrap();
+        prog.connect(c, widen, 1, None).unwrap();
+        assert_fails_typing(&mut prog);
+
+        // And stereo is float only.
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::I64(vec![1, 2]), None)
+            .unwrap();
+        let encode = prog.op_ms_encode_node(None).unwrap();
+        prog.connect(c, encode, 0, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_lerp() {
+        let mut prog = Program::new();
+        let i = prog.add_input(PrimitiveType::F32, 2).unwrap();
+        let read = prog.op_read_input_node(i, None).unwrap();
+        let zero = prog
+            .op_constant_node(Constant::F32(vec![0.0]), None)
+            .unwrap();
+        let t = prog
+            .op_constant_node(Constant::F32(vec![0.25]), None)
+            .unwrap();
+        let lerp = prog.op_lerp_node(None).unwrap();
+        prog.connect(zero, lerp, 0, None).unwrap();
+        prog.connect(read, lerp, 1, None).unwrap();
+        prog.connect(t, lerp, 2, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(typed.get_type(lerp), Some(DataType::new_v_f32(2)));
+
+        // The fraction must be the same type as the endpoints.
+        let mut prog = Program::new();
+        let a = prog
+            .op_constant_node(Constant::F32(vec![0.0]), None)
+            .unwrap();
+        let t = prog
+            .op_constant_node(Constant::F64(vec![0.5]), None)
+            .unwrap();
+        let lerp = prog.op_lerp_node(None).unwrap();
+        prog.connect(a, lerp, 0, None).unwrap();
+        prog.connect(a, lerp, 1, None).unwrap();
+        prog.connect(t, lerp, 2, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    #[test]
+    fn test_target_restrictions() {
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![6]), None).unwrap();
+        let div = prog.op_div_node(None).unwrap();
+        prog.connect(c, div, 0, None).unwrap();
+        prog.connect(c, div, 1, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+
+        let target = TargetSpec {
+            denied_op_primitives: std::borrow::Cow::Borrowed(&[(
+                Op::BinOp(BinOp::Div),
+                PrimitiveType::I64,
+            )]),
+            ..Default::default()
+        };
+        assert!(type_inference_for_target(&prog, &target, &mut diags).is_err());
+        assert!(diags.has_errors());
+
+        // Comparisons produce bools, so the inputs have to be checked as well.
+        let target = TargetSpec {
+            denied_op_primitives: std::borrow::Cow::Borrowed(&[(
+                Op::Compare(CompareOp::Lt),
+                PrimitiveType::F64,
+            )]),
+            ..Default::default()
+        };
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::F64(vec![1.0]), None)
+            .unwrap();
+        let lt = prog.op_lt_node(None).unwrap();
+        prog.connect(c, lt, 0, None).unwrap();
+        prog.connect(c, lt, 1, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert!(type_inference_for_target(&prog, &target, &mut diags).is_err());
+
+        // Other ops are unaffected.
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![6]), None).unwrap();
+        let mul = prog.op_mul_node(None).unwrap();
+        prog.connect(c, mul, 0, None).unwrap();
+        prog.connect(c, mul, 1, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        type_inference_for_target(&prog, &target, &mut diags).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_primitives() {
+        let target = TargetSpec {
+            supported_primitives: std::borrow::Cow::Borrowed(&[PrimitiveType::F32]),
+            ..Default::default()
+        };
+
+        // Neither the constant nor the cast may use i64.
+        let mut prog = Program::new();
+        let c = prog.op_constant_node(Constant::I64(vec![1]), None).unwrap();
+        let cast = prog.op_cast_node(PrimitiveType::F32, None).unwrap();
+        prog.connect(c, cast, 0, None).unwrap();
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert!(type_inference_for_target(&prog, &target, &mut diags).is_err());
+        assert_eq!(diags.iter_level(DiagnosticLevel::Error).count(), 2);
+
+        // But f64 declarations narrowed for an f32 target are fine, even though the reads and writes are still f64.
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F64, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F64, 1).unwrap();
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let negate = prog.op_negate_node(None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(read, negate, 0, None).unwrap();
+        prog.connect(negate, write, 0, None).unwrap();
+        let target = TargetSpec::f32_only();
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert!(type_inference_for_target(&prog, &target, &mut diags).is_err());
+        let mut diags = DiagnosticCollection::new();
+        legalize_for_target(&mut prog, &target, &mut diags).unwrap();
+        type_inference_for_target(&prog, &target, &mut diags).unwrap();
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let mut prog = Program::new();
+        let o = prog.add_output(PrimitiveType::Bool, 2).unwrap();
+        let writer = prog.op_write_output_node(o, None).unwrap();
+        let c1 = prog
+            .op_constant_node(Constant::F32(vec![1.0, 2.0]), None)
+            .unwrap();
+        let c2 = prog
+            .op_constant_node(Constant::F32(vec![1.5]), None)
+            .unwrap();
+        let lt = prog.op_lt_node(None).unwrap();
+        prog.connect(c1, lt, 0, None).unwrap();
+        prog.connect(c2, lt, 1, None).unwrap();
+        prog.connect(lt, writer, 0, None).unwrap();
+
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(lt),
+            Some(DataType::new_v_bool(2).with_rate(Rate::Control))
+        );
+
+        // Bools can be compared for equality, but not ordered.
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::Bool(vec![true]), None)
+            .unwrap();
+        let eq = prog.op_eq_node(None).unwrap();
+        prog.connect(c, eq, 0, None).unwrap();
+        prog.connect(c, eq, 1, None).unwrap();
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(eq),
+            Some(DataType::new_v_bool(1).with_rate(Rate::Control))
+        );
+
+        let mut prog = Program::new();
+        let c = prog
+            .op_constant_node(Constant::Bool(vec![true]), None)
+            .unwrap();
+        let ge = prog.op_ge_node(None).unwrap();
+        prog.connect(c, ge, 0, None).unwrap();
+        prog.connect(c, ge, 1, None).unwrap();
+        assert_fails_typing(&mut prog);
+    }
+
+    /// Build a select, returning the program and the select node.
+    fn build_select(
+        cond: Constant,
+        on_true: Constant,
+        on_false: Constant,
+    ) -> (Program, OperationGraphNode) {
+        let mut prog = Program::new();
+        let select = prog.op_select_node(None).unwrap();
+        for (i, c) in [cond, on_true, on_false].into_iter().enumerate() {
+            let node = prog.op_constant_node(c, None).unwrap();
+            prog.connect(node, select, i, None).unwrap();
+        }
+        (prog, select)
+    }
+
+    #[test]
+    fn test_select() {
+        let (mut prog, select) = build_select(
+            Constant::Bool(vec![true]),
+            Constant::F32(vec![1.0, 2.0]),
+            Constant::F32(vec![3.0]),
+        );
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(select),
+            Some(DataType::new_v_f32(2).with_rate(Rate::Control))
+        );
+
+        let (mut prog, select) = build_select(
+            Constant::Bool(vec![true, false, true]),
+            Constant::I64(vec![1]),
+            Constant::I64(vec![2]),
+        );
+        let typed = type_program(&mut prog);
+        assert_eq!(
+            typed.get_type(select),
+            Some(DataType::new_v_i64(3).with_rate(Rate::Control))
+        );
+
+        // The condition must be a bool.
+        let (mut prog, _) = build_select(
+            Constant::F32(vec![1.0]),
+            Constant::F32(vec![1.0]),
+            Constant::F32(vec![1.0]),
+        );
+        assert_fails_typing(&mut prog);
+
+        // The values must match.
+        let (mut prog, _) = build_select(
+            Constant::Bool(vec![true]),
+            Constant::F32(vec![1.0]),
+            Constant::I64(vec![1]),
+        );
+        assert_fails_typing(&mut prog);
+
+        // And the condition must broadcast with the values.
+        let (mut prog, _) = build_select(
+            Constant::Bool(vec![true, false]),
+            Constant::F32(vec![1.0, 2.0, 3.0]),
+            Constant::F32(vec![1.0]),
+        );
+        assert_fails_typing(&mut prog);
+    }
+
     #[test]
     fn test_no_inputs_to_sr() {
         let mut prog = Program::new();
@@ -149,9 +149,12 @@ impl<'a> VectorUnifier<'a> {
         }
 
         if self.descriptor.width < descriptor.width {
-            self.descriptor = descriptor;
+            self.descriptor.width = descriptor.width;
         }
 
+        // Anything depending on an audio-rate value is itself audio rate.
+        self.descriptor.rate = self.descriptor.rate.max(descriptor.rate);
+
         self.last_node = node;
         Ok(())
     }
@@ -164,6 +167,7 @@ impl<'a> VectorUnifier<'a> {
 #[cfg(test)]
 mod tests {
     pub use super::*;
+    use crate::Rate;
 
     /// Run a unification against a set of types, returning the final result.
     fn run_unification(
@@ -223,6 +227,27 @@ mod tests {
         assert!(run_unification(&[VD::new_f32(0), VD::new_f32(4)], None).is_err());
         assert!(run_unification(&[VD::new_f32(1), VD::new_f32(0)], None).is_err());
 
+        // Mixing rates gives the faster one.
+        assert_eq!(
+            run_unification(
+                &[VD::new_f32(4).with_rate(Rate::Control), VD::new_f32(1)],
+                None
+            )
+            .unwrap(),
+            VD::new_f32(4)
+        );
+        assert_eq!(
+            run_unification(
+                &[
+                    VD::new_f32(1).with_rate(Rate::Control),
+                    VD::new_f32(2).with_rate(Rate::Control)
+                ],
+                None
+            )
+            .unwrap(),
+            VD::new_f32(2).with_rate(Rate::Control)
+        );
+
         // Changing the primitive must also fail.
         assert!(run_unification(&[VD::new_f32(1), VD::new_f64(1)], None).is_err());
     }
@@ -0,0 +1,357 @@
+//! Validate the use of states by [Op::FeedbackRead], [Op::FeedbackWrite], and the generators.
+//!
+//! Feedback goes through a state: the write on one sample is seen by the reads on the next.  For that to mean
+//! anything, every state which is read for feedback must be written exactly once.  A write with no reads is allowed,
+//! since it may be left over from editing, but is warned about.  Feedback delays by exactly one sample, so feedback
+//! states must be of length 1.
+//!
+//! Generators keep their own state, which must be of length 1 and must not be used by anything else.  Oscillators
+//! ([Op::Phasor], [Op::SinOsc]) keep their phase in a float state, and [Op::Noise] keeps its counters in an i64
+//! state.
+use std::collections::BTreeMap;
+
+use crate::*;
+
+#[derive(thiserror::Error, Debug)]
+#[error("Feedback validation failed. Diagnostics have been pushed to the DiagnosticCollection")]
+pub struct FeedbackValidationError;
+
+#[derive(Default)]
+struct StateUses {
+    reads: Vec<OperationGraphNode>,
+    writes: Vec<OperationGraphNode>,
+    generators: Vec<OperationGraphNode>,
+}
+
+/// Check that feedback reads and writes pair up.
+///
+/// If this pass fails, it has pushed the appropriate diagnostics already.
+pub fn validate_feedback(
+    program: &Program,
+    diagnostics: &mut DiagnosticCollection,
+) -> Result<(), FeedbackValidationError> {
+    // A BTreeMap keeps the diagnostics in state order.
+    let mut uses: BTreeMap<usize, StateUses> = BTreeMap::new();
+
+    for n in program.graph.node_indices() {
+        match program.graph.node_weight(n).unwrap().op {
+            Op::FeedbackRead(s) => uses.entry(s).or_default().reads.push(n),
+            Op::FeedbackWrite(s) => uses.entry(s).or_default().writes.push(n),
+            Op::Phasor(s) | Op::SinOsc(s) | Op::Noise(s) => {
+                uses.entry(s).or_default().generators.push(n)
+            }
+            _ => {}
+        }
+    }
+
+    let mut validation_succeeded = true;
+
+    for (state, state_uses) in uses.iter() {
+        if *state >= program.states.len() {
+            let mut builder = DiagnosticBuilder::new(
+                format!(
+                    "Feedback uses state {}, but only {} states available",
+                    state,
+                    program.states.len()
+                ),
+                None,
+            );
+            for n in state_uses
+                .reads
+                .iter()
+                .chain(state_uses.writes.iter())
+                .chain(state_uses.generators.iter())
+            {
+                builder.node_ref("Used here", *n);
+            }
+            diagnostics.add_diagnostic(builder.build(program));
+            validation_succeeded = false;
+            continue;
+        }
+
+        if !state_uses.generators.is_empty() {
+            if !validate_generator_state(program, diagnostics, *state, state_uses) {
+                validation_succeeded = false;
+            }
+            continue;
+        }
+
+        match state_uses.writes.len() {
+            0 => {
+                let mut builder = DiagnosticBuilder::new(
+                    format!("State {} is read for feedback but never written", state),
+                    None,
+                );
+                for n in state_uses.reads.iter() {
+                    builder.node_ref("Read here", *n);
+                }
+                diagnostics.add_diagnostic(builder.build(program));
+                validation_succeeded = false;
+            }
+            1 => {}
+            _ => {
+                let mut builder = DiagnosticBuilder::new(
+                    format!("State {} is written more than once", state),
+                    None,
+                );
+                for n in state_uses.writes.iter() {
+                    builder.node_ref("Written here", *n);
+                }
+                diagnostics.add_diagnostic(builder.build(program));
+                validation_succeeded = false;
+            }
+        }
+
+        let length = program.states[*state].length;
+        if length != 1 {
+            let mut builder = DiagnosticBuilder::new(
+                format!(
+                    "State {} is used for feedback, so must have length 1, not {}",
+                    state, length
+                ),
+                None,
+            );
+            for n in state_uses.reads.iter().chain(state_uses.writes.iter()) {
+                builder.node_ref("Used here", *n);
+            }
+            diagnostics.add_diagnostic(builder.build(program));
+            validation_succeeded = false;
+        }
+
+        if state_uses.reads.is_empty() {
+            let mut builder =
+                DiagnosticBuilder::new(format!("State {} is written but never read", state), None);
+            builder.set_level(DiagnosticLevel::Warning);
+            for n in state_uses.writes.iter() {
+                builder.node_ref("Written here", *n);
+            }
+            diagnostics.add_diagnostic(builder.build(program));
+        }
+    }
+
+    if !validation_succeeded {
+        return Err(FeedbackValidationError);
+    }
+
+    Ok(())
+}
+
+/// Check a state used by a generator, returning whether it is valid.
+fn validate_generator_state(
+    program: &Program,
+    diagnostics: &mut DiagnosticCollection,
+    state: usize,
+    state_uses: &StateUses,
+) -> bool {
+    let mut error = |message: String| {
+        let mut builder = DiagnosticBuilder::new(message, None);
+        for n in state_uses
+            .generators
+            .iter()
+            .chain(state_uses.reads.iter())
+            .chain(state_uses.writes.iter())
+        {
+            builder.node_ref("Used here", *n);
+        }
+        diagnostics.add_diagnostic(builder.build(program));
+    };
+
+    let declared = &program.states[state];
+    let owner = &program
+        .graph
+        .node_weight(state_uses.generators[0])
+        .unwrap()
+        .op;
+    let mut ok = true;
+
+    if state_uses.generators.len() + state_uses.reads.len() + state_uses.writes.len() > 1 {
+        error(format!(
+            "State {} is owned by {}, and cannot be used by anything else",
+            state, owner
+        ));
+        ok = false;
+    }
+
+    let primitive_ok = match owner {
+        Op::Noise(_) => declared.vector.primitive == PrimitiveType::I64,
+        _ => matches!(
+            declared.vector.primitive,
+            PrimitiveType::F32 | PrimitiveType::F64
+        ),
+    };
+    if !primitive_ok {
+        error(format!(
+            "State {} is owned by {}, which cannot use a state of type {}",
+            state, owner, declared.vector.primitive
+        ));
+        ok = false;
+    }
+
+    if declared.length != 1 {
+        error(format!(
+            "State {} is owned by {}, so must have length 1, not {}",
+            state, owner, declared.length
+        ));
+        ok = false;
+    }
+
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a one-pole filter: `out = in + 0.5 * previous out`.
+    #[test]
+    fn test_one_pole() {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F32, 1).unwrap();
+        let state = prog.add_state(PrimitiveType::F32, 1, 1).unwrap();
+
+        let read = prog.op_read_input_node(input, None).unwrap();
+        let prev = prog.op_feedback_read_node(state, None).unwrap();
+        let half = prog
+            .op_constant_node(Constant::F32(vec![0.5]), None)
+            .unwrap();
+        let mul = prog.op_mul_node(None).unwrap();
+        let add = prog.op_add_node(None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        let feedback = prog.op_feedback_write_node(state, None).unwrap();
+
+        prog.connect(prev, mul, 0, None).unwrap();
+        prog.connect(half, mul, 1, None).unwrap();
+        prog.connect(read, add, 0, None).unwrap();
+        prog.connect(mul, add, 1, None).unwrap();
+        prog.connect(add, write, 0, None).unwrap();
+        prog.connect(add, feedback, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        validate_feedback(&prog, &mut diags).unwrap();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        let types = type_inference(&prog, &mut diags).unwrap();
+        assert!(diags.diagnostics.is_empty(), "{}", diags);
+        assert_eq!(types.get_type(prev), Some(DataType::new_v_f32(1)));
+        assert_eq!(types.get_type(feedback), Some(DataType::new_v_f32(1)));
+    }
+
+    #[test]
+    fn test_inconsistent_feedback() {
+        let mut prog = Program::new();
+        let unwritten = prog.add_state(PrimitiveType::F32, 1, 1).unwrap();
+        let twice = prog.add_state(PrimitiveType::F32, 1, 1).unwrap();
+        let unread = prog.add_state(PrimitiveType::F32, 1, 1).unwrap();
+
+        prog.op_feedback_read_node(unwritten, None).unwrap();
+        prog.op_feedback_read_node(twice, None).unwrap();
+        prog.op_feedback_write_node(twice, None).unwrap();
+        prog.op_feedback_write_node(twice, None).unwrap();
+        prog.op_feedback_write_node(unread, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        assert!(validate_feedback(&prog, &mut diags).is_err());
+        assert_eq!(diags.iter_level(DiagnosticLevel::Error).count(), 2);
+        assert_eq!(diags.iter_level(DiagnosticLevel::Warning).count(), 1);
+    }
+
+    #[test]
+    fn test_feedback_state_length() {
+        let mut prog = Program::new();
+        let state = prog.add_state(PrimitiveType::F32, 1, 4).unwrap();
+        let read = prog.op_feedback_read_node(state, None).unwrap();
+        let write = prog.op_feedback_write_node(state, None).unwrap();
+        prog.connect(read, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        assert!(validate_feedback(&prog, &mut diags).is_err());
+        assert_eq!(diags.iter_level(DiagnosticLevel::Error).count(), 1);
+    }
+
+    #[test]
+    fn test_generators() {
+        let mut prog = Program::new();
+        let output = prog.add_output(PrimitiveType::F32, 2).unwrap();
+        let state = prog.add_state(PrimitiveType::F32, 2, 1).unwrap();
+        let freq = prog
+            .op_constant_node(Constant::F32(vec![440.0, 660.0]), None)
+            .unwrap();
+        let offset = prog
+            .op_constant_node(Constant::F32(vec![0.25]), None)
+            .unwrap();
+        let osc = prog.op_sin_osc_node(state, None).unwrap();
+        let write = prog.op_write_output_node(output, None).unwrap();
+        prog.connect(freq, osc, 0, None).unwrap();
+        prog.connect(offset, osc, 1, None).unwrap();
+        prog.connect(osc, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        validate_feedback(&prog, &mut diags).unwrap();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        let types = type_inference(&prog, &mut diags).unwrap();
+        assert!(diags.diagnostics.is_empty(), "{}", diags);
+        assert_eq!(types.get_type(osc), Some(DataType::new_v_f32(2)));
+
+        // Sharing the phase with a second oscillator isn't allowed.
+        let phasor = prog.op_phasor_node(state, None).unwrap();
+        prog.connect(freq, phasor, 0, None).unwrap();
+        assert!(validate_feedback(&prog, &mut diags).is_err());
+    }
+
+    #[test]
+    fn test_oscillator_state_shape() {
+        let mut prog = Program::new();
+        let long = prog.add_state(PrimitiveType::F32, 1, 2).unwrap();
+        let int = prog.add_state(PrimitiveType::I64, 1, 1).unwrap();
+        prog.op_phasor_node(long, None).unwrap();
+        prog.op_phasor_node(int, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        assert!(validate_feedback(&prog, &mut diags).is_err());
+        assert_eq!(diags.iter_level(DiagnosticLevel::Error).count(), 2);
+    }
+
+    #[test]
+    fn test_noise() {
+        let mut prog = Program::new();
+        let state = prog.add_state(PrimitiveType::I64, 2, 1).unwrap();
+        let noise = prog.op_noise_node(state, None).unwrap();
+
+        // A second generator, seeded through its state, produces a different stream from the same graph.
+        let seeded_state = prog.add_state(PrimitiveType::I64, 2, 1).unwrap();
+        prog.set_state_initial(seeded_state, Constant::I64(vec![7, 8]))
+            .unwrap();
+        let seeded = prog.op_noise_node(seeded_state, None).unwrap();
+        for lane in 0..2 {
+            assert_ne!(noise_step(0, lane).1, noise_step(7 + lane as i64, lane).1);
+        }
+
+        let mut diags = DiagnosticCollection::new();
+        validate_feedback(&prog, &mut diags).unwrap();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        let types = type_inference(&prog, &mut diags).unwrap();
+        assert_eq!(types.get_type(noise), Some(DataType::new_v_i64(2)));
+        assert_eq!(types.get_type(seeded), Some(DataType::new_v_i64(2)));
+
+        // Noise counters are integers.
+        let mut prog = Program::new();
+        let state = prog.add_state(PrimitiveType::F32, 1, 1).unwrap();
+        prog.op_noise_node(state, None).unwrap();
+        assert!(validate_feedback(&prog, &mut diags).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_feedback_type() {
+        let mut prog = Program::new();
+        let state = prog.add_state(PrimitiveType::F32, 2, 1).unwrap();
+        let constant = prog
+            .op_constant_node(Constant::F64(vec![1.0, 2.0]), None)
+            .unwrap();
+        let write = prog.op_feedback_write_node(state, None).unwrap();
+        prog.connect(constant, write, 0, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert!(type_inference(&prog, &mut diags).is_err());
+    }
+}
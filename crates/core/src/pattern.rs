@@ -0,0 +1,501 @@
+//! Matching and rewriting of subgraphs.
+//!
+//! Writing algebraic rewrites by hand against petgraph is error prone: each one has to walk inputs, deal with
+//! commutativity, and then rewire edges.  Instead, passes describe what they want with a [Pattern], and hand a
+//! replacement function to [rewrite_all].
+//!
+//! Patterns are trees rooted at the node being rewritten.  Inputs are matched by index, and each input must have
+//! exactly one incoming edge, since multiple edges to an input are an implicit sum that a pattern can't see through.
+//! Commutative operations match their inputs in either order.  Sub-patterns may be captured by name; capturing the
+//! same name twice requires both places to be the same node, which expresses things like `x - x`.
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::*;
+
+type OpPredicate = Box<dyn Fn(&Op) -> bool>;
+
+/// A pattern to match against the graph.  See the module documentation.
+pub struct Pattern {
+    kind: PatternKind,
+    capture: Option<&'static str>,
+}
+
+enum PatternKind {
+    /// Matches any node.
+    Any,
+
+    /// Matches nodes whose op satisfies the predicate.  If `inputs` is empty, the inputs aren't inspected.
+    Op {
+        predicate: OpPredicate,
+        inputs: Vec<Pattern>,
+    },
+}
+
+/// A successful match of a pattern.
+#[derive(Clone, Debug)]
+pub struct PatternMatch {
+    /// The node the pattern matched at.
+    pub root: OperationGraphNode,
+
+    pub captures: HashMap<&'static str, OperationGraphNode>,
+}
+
+impl PatternMatch {
+    /// Get a captured node.
+    ///
+    /// Panics if the capture isn't in the pattern, since that is a bug in the pass.
+    pub fn get(&self, name: &str) -> OperationGraphNode {
+        *self
+            .captures
+            .get(name)
+            .unwrap_or_else(|| panic!("Pattern has no capture named {}", name))
+    }
+}
+
+impl Pattern {
+    /// A pattern which matches any node.
+    pub fn any() -> Self {
+        Self {
+            kind: PatternKind::Any,
+            capture: None,
+        }
+    }
+
+    /// A pattern matching nodes whose op satisfies a predicate.
+    pub fn op(predicate: impl Fn(&Op) -> bool + 'static) -> Self {
+        Self {
+            kind: PatternKind::Op {
+                predicate: Box::new(predicate),
+                inputs: vec![],
+            },
+            capture: None,
+        }
+    }
+
+    /// A pattern matching nodes whose op is exactly `op`.
+    pub fn exact(op: Op) -> Self {
+        Self::op(move |o| *o == op)
+    }
+
+    /// A pattern matching a binary operation with the given inputs.
+    pub fn binop(binop: BinOp, left: Pattern, right: Pattern) -> Self {
+        Self::exact(Op::BinOp(binop))
+            .with_input(left)
+            .with_input(right)
+    }
+
+    /// A pattern matching constants which satisfy a predicate.
+    pub fn constant(predicate: impl Fn(&Constant) -> bool + 'static) -> Self {
+        Self::op(move |o| match o {
+            Op::Constant(c) => predicate(c),
+            _ => false,
+        })
+    }
+
+    /// Add a pattern for the next input of this node.
+    ///
+    /// Panics if this is [Pattern::any], which has no op to have inputs.
+    pub fn with_input(mut self, input: Pattern) -> Self {
+        match &mut self.kind {
+            PatternKind::Any => panic!("Any patterns cannot have inputs"),
+            PatternKind::Op { inputs, .. } => inputs.push(input),
+        }
+        self
+    }
+
+    /// Capture the node matched by this pattern under the given name.
+    pub fn capture(mut self, name: &'static str) -> Self {
+        self.capture = Some(name);
+        self
+    }
+
+    /// Try to match this pattern at a node.
+    pub fn match_at(&self, program: &Program, node: OperationGraphNode) -> Option<PatternMatch> {
+        let mut captures = HashMap::new();
+        if self.match_node(program, node, &mut captures) {
+            Some(PatternMatch {
+                root: node,
+                captures,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn match_node(
+        &self,
+        program: &Program,
+        node: OperationGraphNode,
+        captures: &mut HashMap<&'static str, OperationGraphNode>,
+    ) -> bool {
+        if let Some(name) = self.capture {
+            match captures.get(name) {
+                Some(n) if *n != node => return false,
+                Some(_) => {}
+                None => {
+                    captures.insert(name, node);
+                }
+            }
+        }
+
+        let (predicate, inputs) = match &self.kind {
+            PatternKind::Any => return true,
+            PatternKind::Op { predicate, inputs } => (predicate, inputs),
+        };
+
+        let op = &program
+            .graph
+            .node_weight(node)
+            .expect("Node should exist")
+            .op;
+        if !predicate(op) {
+            return false;
+        }
+
+        if inputs.is_empty() {
+            return true;
+        }
+
+        let materialized = MaterializedInputs::materialize_data_inputs(program, node);
+        if materialized.inputs.len() != inputs.len() {
+            return false;
+        }
+
+        let mut sources = vec![];
+        for i in 0..inputs.len() {
+            match materialized.get_input(i) {
+                [single] => sources.push(single.source_node),
+                _ => return false,
+            }
+        }
+
+        let saved = captures.clone();
+        if Self::match_inputs(program, inputs.iter(), sources.iter().cloned(), captures) {
+            return true;
+        }
+
+        if inputs.len() == 2 && op.get_descriptor().commutative {
+            *captures = saved;
+            return Self::match_inputs(
+                program,
+                inputs.iter(),
+                sources.iter().rev().cloned(),
+                captures,
+            );
+        }
+
+        false
+    }
+
+    fn match_inputs<'p>(
+        program: &Program,
+        patterns: impl Iterator<Item = &'p Pattern>,
+        sources: impl Iterator<Item = OperationGraphNode>,
+        captures: &mut HashMap<&'static str, OperationGraphNode>,
+    ) -> bool {
+        patterns
+            .zip(sources)
+            .all(|(p, s)| p.match_node(program, s, captures))
+    }
+}
+
+/// Find all the places in the program where a pattern matches.
+///
+/// Matches may overlap.
+pub fn find_matches(program: &Program, pattern: &Pattern) -> Vec<PatternMatch> {
+    program
+        .graph
+        .node_indices()
+        .filter_map(|n| pattern.match_at(program, n))
+        .collect()
+}
+
+/// How many rewrites [rewrite_all] may perform per node in the program before it assumes it will never finish.
+pub const MAX_REWRITES_PER_NODE: usize = 16;
+
+/// Repeatedly rewrite the program until the pattern no longer matches anywhere.
+///
+/// `replace` is given each match, and returns the node which should replace the match's root, building any new nodes
+/// it needs.  All outgoing edges of the root are moved to the replacement with [Program::move_outgoing_edges], and the
+/// root is then removed.  Other nodes of the match are left alone, since they may have other consumers; nodes which end
+/// up unused are left for [dead_code_elimination] to clean up.
+///
+/// The start and final nodes are never rewritten.  It is an error for the replacement to depend on the root, since that
+/// would make a cycle, and for rewriting not to finish within [MAX_REWRITES_PER_NODE] rewrites per node, which happens
+/// when replacements match the pattern again.  On error, the program is left partially rewritten.
+///
+/// Returns how many rewrites were performed.
+pub fn rewrite_all(
+    program: &mut Program,
+    pattern: &Pattern,
+    mut replace: impl FnMut(&mut Program, &PatternMatch) -> Result<OperationGraphNode>,
+) -> Result<usize> {
+    let limit = program.graph.node_count() * MAX_REWRITES_PER_NODE;
+    let mut count = 0;
+
+    while let Some(m) = program
+        .graph
+        .node_indices()
+        .filter(|n| *n != program.start_node && *n != program.final_node)
+        .find_map(|n| pattern.match_at(program, n))
+    {
+        if count == limit {
+            anyhow::bail!(
+                "Rewriting did not finish after {} rewrites; does the replacement match the pattern again?",
+                count
+            );
+        }
+
+        let replacement = replace(program, &m)?;
+        if replacement == m.root {
+            anyhow::bail!("A rewrite must not replace a node with itself");
+        }
+
+        if petgraph::algo::has_path_connecting(&program.graph, m.root, replacement, None) {
+            anyhow::bail!("A rewrite must not replace a node with something depending on it");
+        }
+
+        program.move_outgoing_edges(m.root, replacement)?;
+        program.graph.remove_node(m.root);
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_all_ones(c: &Constant) -> bool {
+        match c {
+            Constant::F32(v) => v.iter().all(|x| *x == 1.0),
+            _ => false,
+        }
+    }
+
+    /// Build `input -> <body> -> output`, returning the program, the read, and the write.
+    fn build(
+        body: impl FnOnce(&mut Program, OperationGraphNode) -> OperationGraphNode,
+    ) -> (Program, OperationGraphNode, OperationGraphNode) {
+        let mut prog = Program::new();
+        let i = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let o = prog.add_output(PrimitiveType::F32, 1).unwrap();
+        let read = prog.op_read_input_node(i, None).unwrap();
+        let write = prog.op_write_output_node(o, None).unwrap();
+        let last = body(&mut prog, read);
+        prog.connect(last, write, 0, None).unwrap();
+        (prog, read, write)
+    }
+
+    fn single_source(prog: &Program, node: OperationGraphNode) -> OperationGraphNode {
+        MaterializedInputs::materialize(prog, node).get_input(0)[0].source_node
+    }
+
+    #[test]
+    fn test_double_negation() {
+        let (mut prog, read, write) = build(|prog, read| {
+            let n1 = prog.op_negate_node(None).unwrap();
+            let n2 = prog.op_negate_node(None).unwrap();
+            prog.connect(read, n1, 0, None).unwrap();
+            prog.connect(n1, n2, 0, None).unwrap();
+            n2
+        });
+
+        let pattern = Pattern::exact(Op::Negate)
+            .with_input(Pattern::exact(Op::Negate).with_input(Pattern::any().capture("x")));
+        let count = rewrite_all(&mut prog, &pattern, |_, m| Ok(m.get("x"))).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(single_source(&prog, write), read, "{}", prog.graphviz());
+    }
+
+    #[test]
+    fn test_commutative_matching() {
+        let pattern = Pattern::binop(
+            BinOp::Mul,
+            Pattern::any().capture("x"),
+            Pattern::constant(is_all_ones),
+        );
+
+        // The constant is on the left here, but mul is commutative so it should still match.
+        let (mut prog, read, write) = build(|prog, read| {
+            let one = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            let mul = prog.op_mul_node(None).unwrap();
+            prog.connect(one, mul, 0, None).unwrap();
+            prog.connect(read, mul, 1, None).unwrap();
+            mul
+        });
+        assert_eq!(find_matches(&prog, &pattern).len(), 1);
+        rewrite_all(&mut prog, &pattern, |_, m| Ok(m.get("x"))).unwrap();
+        assert_eq!(single_source(&prog, write), read);
+
+        // But div isn't, and so `1 / x` must not match `x / 1`.
+        let pattern = Pattern::binop(
+            BinOp::Div,
+            Pattern::any().capture("x"),
+            Pattern::constant(is_all_ones),
+        );
+        let (prog, _, _) = build(|prog, read| {
+            let one = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            let div = prog.op_div_node(None).unwrap();
+            prog.connect(one, div, 0, None).unwrap();
+            prog.connect(read, div, 1, None).unwrap();
+            div
+        });
+        assert!(find_matches(&prog, &pattern).is_empty());
+    }
+
+    #[test]
+    fn test_repeated_captures() {
+        let pattern = Pattern::binop(
+            BinOp::Sub,
+            Pattern::any().capture("x"),
+            Pattern::any().capture("x"),
+        );
+
+        let (prog, _, _) = build(|prog, read| {
+            let sub = prog.op_sub_node(None).unwrap();
+            prog.connect(read, sub, 0, None).unwrap();
+            prog.connect(read, sub, 1, None).unwrap();
+            sub
+        });
+        assert_eq!(find_matches(&prog, &pattern).len(), 1);
+
+        let (prog, _, _) = build(|prog, read| {
+            let other = prog.op_clock_node(None).unwrap();
+            let sub = prog.op_sub_node(None).unwrap();
+            prog.connect(read, sub, 0, None).unwrap();
+            prog.connect(other, sub, 1, None).unwrap();
+            sub
+        });
+        assert!(find_matches(&prog, &pattern).is_empty());
+    }
+
+    #[test]
+    fn test_orphans_are_collected() {
+        // Rewriting `x * 1` leaves the constant connected only to the start node.
+        let (mut prog, read, write) = build(|prog, read| {
+            let one = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            let mul = prog.op_mul_node(None).unwrap();
+            prog.connect(read, mul, 0, None).unwrap();
+            prog.connect(one, mul, 1, None).unwrap();
+            mul
+        });
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+
+        let pattern = Pattern::binop(
+            BinOp::Mul,
+            Pattern::any().capture("x"),
+            Pattern::constant(is_all_ones),
+        );
+        rewrite_all(&mut prog, &pattern, |_, m| Ok(m.get("x"))).unwrap();
+
+        assert_eq!(dead_code_elimination(&mut prog, &mut diags), 1);
+        // Start, final, the read, and the write.
+        assert_eq!(prog.graph.node_count(), 4, "{}", prog.graphviz());
+        assert_eq!(single_source(&prog, write), read);
+        assert!(!diags.has_errors(), "{}", diags);
+    }
+
+    #[test]
+    fn test_rewriting_into_a_summed_input() {
+        // `x * 1 + x`, with the sum implicit in the write.
+        let (mut prog, read, write) = build(|prog, read| {
+            let one = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            let mul = prog.op_mul_node(None).unwrap();
+            prog.connect(read, mul, 0, None).unwrap();
+            prog.connect(one, mul, 1, None).unwrap();
+            mul
+        });
+        prog.connect(read, write, 0, None).unwrap();
+
+        let pattern = Pattern::binop(
+            BinOp::Mul,
+            Pattern::any().capture("x"),
+            Pattern::constant(is_all_ones),
+        );
+        rewrite_all(&mut prog, &pattern, |_, m| Ok(m.get("x"))).unwrap();
+
+        // The two edges from the read become an explicit `x + x`.
+        let add = single_source(&prog, write);
+        assert_eq!(prog.graph[add].op, Op::BinOp(BinOp::Add));
+        let sources = MaterializedInputs::materialize(&prog, add);
+        assert_eq!(sources.get_input(0)[0].source_node, read);
+        assert_eq!(sources.get_input(1)[0].source_node, read);
+        assert!(!prog.graph.contains_edge(read, write));
+    }
+
+    #[test]
+    fn test_bad_rewrites() {
+        let (mut prog, _, _) = build(|_, read| read);
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+
+        // The start and final nodes are never matched.
+        let count = rewrite_all(
+            &mut prog,
+            &Pattern::op(|o| o.is_start() || o.is_final()),
+            |p, _| p.op_clock_node(None),
+        )
+        .unwrap();
+        assert_eq!(count, 0);
+        assert!(prog.graph.node_weight(prog.start_node).is_some());
+        assert!(prog.graph.node_weight(prog.final_node).is_some());
+
+        // Replacing a negation with another negation never finishes.
+        let (mut prog, _, _) = build(|prog, read| {
+            let neg = prog.op_negate_node(None).unwrap();
+            prog.connect(read, neg, 0, None).unwrap();
+            neg
+        });
+        let pattern = Pattern::exact(Op::Negate).with_input(Pattern::any().capture("x"));
+        assert!(rewrite_all(&mut prog, &pattern, |p, m| {
+            let neg = p.op_negate_node(None)?;
+            p.connect(m.get("x"), neg, 0, None)?;
+            Ok(neg)
+        })
+        .is_err());
+
+        // Replacing a node with one of its consumers would make a cycle.
+        let (mut prog, _, write) = build(|prog, read| {
+            let neg = prog.op_negate_node(None).unwrap();
+            prog.connect(read, neg, 0, None).unwrap();
+            neg
+        });
+        let pattern = Pattern::exact(Op::Negate);
+        assert!(rewrite_all(&mut prog, &pattern, |p, m| {
+            let abs = p.op_abs_node(None)?;
+            p.connect(m.root, abs, 0, None)?;
+            Ok(abs)
+        })
+        .is_err());
+        assert!(prog.topological_sort().is_ok());
+        assert!(prog.graph.node_weight(write).is_some());
+    }
+
+    #[test]
+    fn test_summed_inputs_dont_match() {
+        let pattern = Pattern::exact(Op::Negate).with_input(Pattern::any());
+        let (prog, _, _) = build(|prog, read| {
+            let clock = prog.op_clock_node(None).unwrap();
+            let neg = prog.op_negate_node(None).unwrap();
+            prog.connect(read, neg, 0, None).unwrap();
+            prog.connect(clock, neg, 0, None).unwrap();
+            neg
+        });
+        assert!(find_matches(&prog, &pattern).is_empty());
+    }
+}
@@ -18,12 +18,29 @@ pub type OperationGraphEdgeIndex = petgraph::graph::EdgeIndex;
 ///
 /// The fields of this struct are public due to our desire to split things into different crates.  Rust borrowing
 /// limitations require this for field splitting.
-#[derive(Debug)]
+///
+/// Cloning is a deep copy which preserves node and edge indices.  To compare programs, see [Program::structurally_eq].
+#[derive(Clone, Debug)]
 pub struct Program {
     pub inputs: Vec<VectorDescriptor>,
     pub outputs: Vec<VectorDescriptor>,
-    pub properties: Vec<PrimitiveType>,
+
+    /// Optional names for the inputs, parallel to `inputs`.
+    pub input_names: Vec<Option<String>>,
+
+    /// Optional names for the outputs, parallel to `outputs`.
+    pub output_names: Vec<Option<String>>,
+
+    pub properties: Vec<PropertyDescriptor>,
+    pub buffer_inputs: Vec<BufferInput>,
     pub states: Vec<State>,
+
+    /// Limits on the memory used by `states`, enforced by [Program::add_state].
+    ///
+    /// States pushed directly, or limits changed after states were added, are caught by
+    /// [Program::verify_state_limits].
+    pub state_limits: StateLimits,
+
     pub graph: OperationGraph,
 
     /// The start node, e.g. [Op::Start].
@@ -45,6 +62,22 @@ macro_rules! decl_binop_method {
     };
 }
 
+macro_rules! decl_unary_method {
+    ($name: ident, $op: ident) => {
+        pub fn $name(&mut self, source_loc: Option<SourceLoc>) -> Result<OperationGraphNode> {
+            Ok(self.op_node(Op::UnaryOp(UnaryOp::$op), source_loc))
+        }
+    };
+}
+
+macro_rules! decl_compare_method {
+    ($name: ident, $op: ident) => {
+        pub fn $name(&mut self, source_loc: Option<SourceLoc>) -> Result<OperationGraphNode> {
+            Ok(self.op_node(Op::Compare(CompareOp::$op), source_loc))
+        }
+    };
+}
+
 macro_rules! decl_simple_op_method {
     ($name: ident, $op: ident) => {
         pub fn $name(&mut self, source_loc: Option<SourceLoc>) -> Result<OperationGraphNode> {
@@ -71,8 +104,12 @@ impl Program {
         Program {
             inputs: vec![],
             outputs: vec![],
+            input_names: vec![],
+            output_names: vec![],
             properties: vec![],
+            buffer_inputs: vec![],
             states: vec![],
+            state_limits: Default::default(),
             graph,
             start_node,
             final_node,
@@ -83,11 +120,37 @@ impl Program {
     ///
     /// Return the index to this input.
     pub fn add_input(&mut self, primitive: PrimitiveType, width: u64) -> Result<usize> {
+        self.add_input_impl(None, primitive, width)
+    }
+
+    /// Add an input with a name, which must not already be used by another input.
+    pub fn add_named_input(
+        &mut self,
+        name: impl Into<String>,
+        primitive: PrimitiveType,
+        width: u64,
+    ) -> Result<usize> {
+        self.add_input_impl(Some(name.into()), primitive, width)
+    }
+
+    fn add_input_impl(
+        &mut self,
+        name: Option<String>,
+        primitive: PrimitiveType,
+        width: u64,
+    ) -> Result<usize> {
         if width == 0 {
             anyhow::bail!("Inputs must not be of zero width");
         }
 
-        self.inputs.push(VectorDescriptor { primitive, width });
+        if let Some(n) = name.as_ref() {
+            if self.input_names.iter().flatten().any(|x| x == n) {
+                anyhow::bail!("Duplicate input name {}", n);
+            }
+        }
+
+        self.inputs.push(VectorDescriptor::new(primitive, width));
+        self.input_names.push(name);
         Ok(self.inputs.len() - 1)
     }
 
@@ -97,22 +160,192 @@ impl Program {
     ///
     /// Returns the index to the new output.
     pub fn add_output(&mut self, primitive: PrimitiveType, width: u64) -> Result<usize> {
+        self.add_output_impl(None, primitive, width)
+    }
+
+    /// Add an output with a name, which must not already be used by another output.
+    pub fn add_named_output(
+        &mut self,
+        name: impl Into<String>,
+        primitive: PrimitiveType,
+        width: u64,
+    ) -> Result<usize> {
+        self.add_output_impl(Some(name.into()), primitive, width)
+    }
+
+    fn add_output_impl(
+        &mut self,
+        name: Option<String>,
+        primitive: PrimitiveType,
+        width: u64,
+    ) -> Result<usize> {
         if width == 0 {
             anyhow::bail!("Outputs must not be of zero width");
         }
 
-        self.outputs.push(VectorDescriptor { primitive, width });
+        if let Some(n) = name.as_ref() {
+            if self.output_names.iter().flatten().any(|x| x == n) {
+                anyhow::bail!("Duplicate output name {}", n);
+            }
+        }
+
+        self.outputs.push(VectorDescriptor::new(primitive, width));
+        self.output_names.push(name);
         Ok(self.outputs.len() - 1)
     }
 
+    /// Add a buffer input holding `length` frames of the given primitive and width.
+    ///
+    /// Returns the index of the new buffer input.
+    pub fn add_buffer_input(
+        &mut self,
+        primitive: PrimitiveType,
+        width: u64,
+        length: u64,
+    ) -> Result<usize> {
+        if width == 0 {
+            anyhow::bail!("Buffer inputs must not be of zero width");
+        }
+
+        if length == 0 {
+            anyhow::bail!("Buffer inputs must not be of zero length");
+        }
+
+        self.buffer_inputs.push(BufferInput {
+            vector: VectorDescriptor::new(primitive, width),
+            length,
+        });
+        Ok(self.buffer_inputs.len() - 1)
+    }
+
+    /// Describe an input for diagnostics, e.g. `0 (left)`, or just `0` if the input has no name.
+    pub fn input_label(&self, input: usize) -> String {
+        label(
+            input,
+            self.input_names.get(input).and_then(|x| x.as_deref()),
+        )
+    }
+
+    /// Describe an output for diagnostics.  See [Program::input_label].
+    pub fn output_label(&self, output: usize) -> String {
+        label(
+            output,
+            self.output_names.get(output).and_then(|x| x.as_deref()),
+        )
+    }
+
+    /// Describe a property for diagnostics.  See [Program::input_label].
+    pub fn property_label(&self, property: usize) -> String {
+        label(
+            property,
+            self.properties.get(property).map(|x| x.name.as_str()),
+        )
+    }
+
     /// Add a property, a scalar input to the program.
     ///
-    /// Return the index of the new property.
-    pub fn add_property(&mut self, primitive: PrimitiveType) -> Result<usize> {
-        self.properties.push(primitive);
+    /// The descriptor must be valid, and its name must not already be in use.  Return the index of the new property.
+    pub fn add_property(&mut self, descriptor: PropertyDescriptor) -> Result<usize> {
+        descriptor.validate()?;
+
+        if self.property_index(&descriptor.name).is_some() {
+            anyhow::bail!("Duplicate property name {}", descriptor.name);
+        }
+
+        self.properties.push(descriptor);
         Ok(self.properties.len() - 1)
     }
 
+    /// Iterate over the properties, with their indices.
+    pub fn iter_properties(&self) -> impl Iterator<Item = (usize, &PropertyDescriptor)> {
+        self.properties.iter().enumerate()
+    }
+
+    /// Find the index of a property by name.
+    pub fn property_index(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name == name)
+    }
+
+    /// Add a state holding `length` vectors of the given primitive and width.
+    ///
+    /// Returns the index of the new state.  If the state doesn't fit in [Program::state_limits], the error is a
+    /// [StateLimitError].
+    pub fn add_state(
+        &mut self,
+        primitive: PrimitiveType,
+        width: u64,
+        length: u64,
+    ) -> Result<usize> {
+        if width == 0 {
+            anyhow::bail!("States must not be of zero width");
+        }
+
+        if length == 0 {
+            anyhow::bail!("States must not be of zero length");
+        }
+
+        self.states.push(State {
+            vector: VectorDescriptor::new(primitive, width),
+            length,
+            initial: None,
+        });
+        let index = self.states.len() - 1;
+
+        if let Some(e) = self
+            .state_limits
+            .check(&self.states)
+            .into_iter()
+            .find(|e| e.state() == index)
+        {
+            self.states.pop();
+            return Err(e.into());
+        }
+
+        Ok(index)
+    }
+
+    /// Set the value a state starts at; see [State::initial].
+    ///
+    /// For example, the initial value of a noise state holds the generator's counters, one per lane, and so seeds it.
+    pub fn set_state_initial(&mut self, state: usize, initial: Constant) -> Result<()> {
+        let Some(s) = self.states.get_mut(state) else {
+            anyhow::bail!(
+                "Attempt to set the initial value of state {} but only {} states are available",
+                state,
+                self.states.len()
+            );
+        };
+
+        if initial.primitive_type() != s.vector.primitive {
+            anyhow::bail!(
+                "State {} holds {}, but the initial value is {}",
+                state,
+                s.vector.primitive,
+                initial.primitive_type()
+            );
+        }
+
+        if initial.width() != 1 && initial.width() != s.vector.width {
+            anyhow::bail!(
+                "State {} is of width {}, but the initial value is of width {}",
+                state,
+                s.vector.width,
+                initial.width()
+            );
+        }
+
+        s.initial = Some(initial);
+        Ok(())
+    }
+
+    /// Check all states against [Program::state_limits], returning the first which doesn't fit.
+    pub fn verify_state_limits(&self) -> Result<(), StateLimitError> {
+        match self.state_limits.check(&self.states).into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
     /// Connect a node to the given input of another node.
     ///
     /// All nodes currently have one output only.
@@ -155,6 +388,49 @@ impl Program {
         Ok(())
     }
 
+    /// Move all outgoing edges of `from` so that they come from `to` instead.
+    ///
+    /// If `to` is already connected to the same input of some target, the two edges would be a duplicate, which
+    /// [Program::connect] disallows.  Since they are an implicit sum, they are replaced with an explicit add of `to`
+    /// with itself.
+    pub fn move_outgoing_edges(
+        &mut self,
+        from: OperationGraphNode,
+        to: OperationGraphNode,
+    ) -> Result<()> {
+        if self.graph.node_weight(from).is_none() || self.graph.node_weight(to).is_none() {
+            anyhow::bail!("Graph doesn't contain the nodes to move edges between");
+        }
+
+        let outgoing = self
+            .graph
+            .edges_directed(from, Direction::Outgoing)
+            .map(|e| (e.id(), e.target(), e.weight().clone()))
+            .collect::<Vec<_>>();
+
+        for (id, target, weight) in outgoing {
+            self.graph.remove_edge(id);
+
+            let existing = self
+                .graph
+                .edges_directed(target, Direction::Incoming)
+                .find(|e| e.source() == to && e.weight().input == weight.input)
+                .map(|e| e.id());
+            let Some(existing) = existing else {
+                self.graph.add_edge(to, target, weight);
+                continue;
+            };
+
+            self.graph.remove_edge(existing);
+            let add = self.op_add_node(weight.source_loc.clone())?;
+            self.connect(to, add, 0, weight.source_loc.clone())?;
+            self.connect(to, add, 1, weight.source_loc.clone())?;
+            self.graph.add_edge(add, target, weight);
+        }
+
+        Ok(())
+    }
+
     fn op_node(&mut self, op: Op, source_loc: Option<SourceLoc>) -> OperationGraphNode {
         let n = Node { op, source_loc };
         self.graph.add_node(n)
@@ -164,7 +440,36 @@ impl Program {
     decl_binop_method!(op_sub_node, Sub);
     decl_binop_method!(op_mul_node, Mul);
     decl_binop_method!(op_div_node, Div);
+    decl_binop_method!(op_logical_and_node, LogicalAnd);
+    decl_binop_method!(op_logical_or_node, LogicalOr);
+    decl_binop_method!(op_bit_and_node, BitAnd);
+    decl_binop_method!(op_bit_or_node, BitOr);
+    decl_binop_method!(op_bit_xor_node, BitXor);
+    decl_binop_method!(op_shl_node, Shl);
+    decl_binop_method!(op_shr_node, Shr);
+    decl_unary_method!(op_sqrt_node, Sqrt);
+    decl_unary_method!(op_exp_node, Exp);
+    decl_unary_method!(op_log_node, Log);
+    decl_unary_method!(op_abs_node, Abs);
+    decl_unary_method!(op_floor_node, Floor);
+    decl_unary_method!(op_ceil_node, Ceil);
+    decl_unary_method!(op_round_node, Round);
+    decl_unary_method!(op_trunc_node, Trunc);
+    decl_unary_method!(op_fract_node, Fract);
+    decl_compare_method!(op_eq_node, Eq);
+    decl_compare_method!(op_ne_node, Ne);
+    decl_compare_method!(op_lt_node, Lt);
+    decl_compare_method!(op_le_node, Le);
+    decl_compare_method!(op_gt_node, Gt);
+    decl_compare_method!(op_ge_node, Ge);
+    decl_simple_op_method!(op_select_node, Select);
+    decl_simple_op_method!(op_lerp_node, Lerp);
+    decl_simple_op_method!(op_ms_encode_node, MsEncode);
+    decl_simple_op_method!(op_ms_decode_node, MsDecode);
+    decl_simple_op_method!(op_stereo_width_node, StereoWidth);
     decl_simple_op_method!(op_negate_node, Negate);
+    decl_simple_op_method!(op_logical_not_node, LogicalNot);
+    decl_simple_op_method!(op_bit_not_node, BitNot);
     decl_simple_op_method!(op_clock_node, Clock);
     decl_simple_op_method!(op_sr_node, Sr);
 
@@ -184,6 +489,22 @@ impl Program {
         Ok(self.op_node(Op::ReadInput(input), source_loc))
     }
 
+    pub fn op_read_input_indexed_node(
+        &mut self,
+        buffer: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if buffer >= self.buffer_inputs.len() {
+            anyhow::bail!(
+                "Attempt to read buffer input {} but only {} buffer inputs are available",
+                buffer,
+                self.buffer_inputs.len()
+            );
+        }
+
+        Ok(self.op_node(Op::ReadInputIndexed(buffer), source_loc))
+    }
+
     pub fn op_read_property_node(
         &mut self,
         property: usize,
@@ -216,6 +537,86 @@ impl Program {
         Ok(self.op_node(Op::WriteOutput(output), source_loc))
     }
 
+    pub fn op_feedback_read_node(
+        &mut self,
+        state: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if state >= self.states.len() {
+            anyhow::bail!(
+                "Attempt to read state {} but only {} states are available",
+                state,
+                self.states.len()
+            );
+        }
+
+        Ok(self.op_node(Op::FeedbackRead(state), source_loc))
+    }
+
+    pub fn op_feedback_write_node(
+        &mut self,
+        state: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if state >= self.states.len() {
+            anyhow::bail!(
+                "Attempt to write state {} but only {} states are available",
+                state,
+                self.states.len()
+            );
+        }
+
+        Ok(self.op_node(Op::FeedbackWrite(state), source_loc))
+    }
+
+    pub fn op_phasor_node(
+        &mut self,
+        state: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if state >= self.states.len() {
+            anyhow::bail!(
+                "Attempt to use state {} for a phasor but only {} states are available",
+                state,
+                self.states.len()
+            );
+        }
+
+        Ok(self.op_node(Op::Phasor(state), source_loc))
+    }
+
+    pub fn op_sin_osc_node(
+        &mut self,
+        state: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if state >= self.states.len() {
+            anyhow::bail!(
+                "Attempt to use state {} for an oscillator but only {} states are available",
+                state,
+                self.states.len()
+            );
+        }
+
+        Ok(self.op_node(Op::SinOsc(state), source_loc))
+    }
+
+    pub fn op_noise_node(
+        &mut self,
+        state: usize,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        if state >= self.states.len() {
+            anyhow::bail!(
+                "Attempt to use state {} for noise but only {} states are available",
+                state,
+                self.states.len()
+            );
+        }
+
+        Ok(self.op_node(Op::Noise(state), source_loc))
+    }
+
     pub fn op_cast_node(
         &mut self,
         to_ty: PrimitiveType,
@@ -224,6 +625,14 @@ impl Program {
         Ok(self.op_node(Op::Cast(to_ty), source_loc))
     }
 
+    pub fn op_float_to_int_node(
+        &mut self,
+        mode: RoundingMode,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<OperationGraphNode> {
+        Ok(self.op_node(Op::FloatToInt(mode), source_loc))
+    }
+
     pub fn op_constant_node(
         &mut self,
         constant: Constant,
@@ -253,8 +662,39 @@ impl Program {
     }
 
     /// Build a graphviz string for debugging purposes.
+    ///
+    /// Nodes reading inputs and properties or writing outputs are labeled with their names, if any.
     pub fn graphviz(&self) -> String {
-        petgraph::dot::Dot::new(&self.graph).to_string()
+        use petgraph::dot::{Config, Dot};
+
+        let node_attrs = |_, (_, node): (OperationGraphNode, &Node)| {
+            let name = match node.op {
+                Op::ReadInput(i) => self.input_names.get(i).cloned().flatten(),
+                Op::WriteOutput(o) => self.output_names.get(o).cloned().flatten(),
+                Op::ReadProperty(p) => self.properties.get(p).map(|x| x.name.clone()),
+                _ => None,
+            };
+            let label = match name {
+                Some(n) => format!("{} [{}]", node, n),
+                None => node.to_string(),
+            };
+            format!("label = \"{}\"", label.escape_debug())
+        };
+
+        Dot::with_attr_getters(
+            &self.graph,
+            &[Config::NodeNoLabel],
+            &|_, _| String::new(),
+            &node_attrs,
+        )
+        .to_string()
+    }
+}
+
+fn label(index: usize, name: Option<&str>) -> String {
+    match name {
+        Some(n) => format!("{} ({})", index, n),
+        None => index.to_string(),
     }
 }
 
@@ -282,4 +722,89 @@ mod tests {
         // But a duplicate edge to a different input should be fine.
         program.connect(n1, n2, 1, None).unwrap();
     }
+
+    #[test]
+    fn test_state_initial() {
+        let mut program = Program::new();
+        let state = program.add_state(PrimitiveType::I64, 2, 1).unwrap();
+        assert_eq!(program.states[state].initial, None);
+
+        program
+            .set_state_initial(state, Constant::I64(vec![1, 2]))
+            .unwrap();
+        program
+            .set_state_initial(state, Constant::I64(vec![3]))
+            .unwrap();
+        assert_eq!(program.states[state].initial, Some(Constant::I64(vec![3])));
+
+        assert!(program
+            .set_state_initial(state, Constant::F32(vec![1.0]))
+            .is_err());
+        assert!(program
+            .set_state_initial(state, Constant::I64(vec![1, 2, 3]))
+            .is_err());
+        assert!(program
+            .set_state_initial(state + 1, Constant::I64(vec![1]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_state_limits() {
+        let mut program = Program::new();
+        let err = program
+            .add_state(PrimitiveType::F64, u64::MAX, u64::MAX)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StateLimitError>(),
+            Some(&StateLimitError::StateTooLarge {
+                state: 0,
+                bytes: u64::MAX,
+                limit: 1 << 30
+            })
+        );
+        assert!(program.states.is_empty());
+
+        program.state_limits = StateLimits {
+            max_state_bytes: Some(80),
+            max_total_state_bytes: Some(100),
+        };
+        program.add_state(PrimitiveType::F64, 1, 10).unwrap();
+        let err = program.add_state(PrimitiveType::F32, 1, 6).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StateLimitError>(),
+            Some(&StateLimitError::TotalTooLarge {
+                state: 1,
+                bytes: 104,
+                limit: 100
+            })
+        );
+        program.add_state(PrimitiveType::F32, 1, 5).unwrap();
+        program.verify_state_limits().unwrap();
+
+        // Tightening the limits afterwards is caught by verification.
+        program.state_limits.max_state_bytes = Some(40);
+        assert_eq!(program.verify_state_limits().unwrap_err().state(), 0);
+    }
+
+    #[test]
+    fn test_names() {
+        let mut program = Program::new();
+        let left = program
+            .add_named_input("left", PrimitiveType::F32, 1)
+            .unwrap();
+        let anon = program.add_input(PrimitiveType::F32, 1).unwrap();
+        assert!(program
+            .add_named_input("left", PrimitiveType::F32, 1)
+            .is_err());
+        let out = program
+            .add_named_output("left", PrimitiveType::F32, 1)
+            .unwrap();
+
+        assert_eq!(program.input_label(left), "0 (left)");
+        assert_eq!(program.input_label(anon), "1");
+        assert_eq!(program.output_label(out), "0 (left)");
+
+        program.op_read_input_node(left, None).unwrap();
+        assert!(program.graphviz().contains("ReadInput(0)) [left]"));
+    }
 }
@@ -0,0 +1,156 @@
+//! Metadata describing properties.
+use anyhow::Result;
+
+use crate::PrimitiveType;
+
+/// Describes a property: a scalar which the host sets and the program reads.
+///
+/// Values are given as f64 whatever the primitive, since that is what hosts hand us.  Bools use 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertyDescriptor {
+    /// The name hosts know this property by.  Must be unique within a program.
+    pub name: String,
+
+    pub primitive: PrimitiveType,
+
+    /// The smallest value the host may set.
+    pub min: f64,
+
+    /// The largest value the host may set.
+    pub max: f64,
+
+    /// The value of the property until the host sets one.
+    pub default: f64,
+
+    /// How long, in seconds, changes to the property should be smoothed over.  0 means changes apply immediately.
+    ///
+    /// Only float properties may be smoothed.
+    pub smoothing: f64,
+}
+
+impl PropertyDescriptor {
+    /// A property with an unbounded range (0 to 1 for bools), a default of 0, and no smoothing.
+    pub fn new(name: impl Into<String>, primitive: PrimitiveType) -> Self {
+        let (min, max) = match primitive {
+            PrimitiveType::Bool => (0.0, 1.0),
+            _ => (f64::NEG_INFINITY, f64::INFINITY),
+        };
+
+        Self {
+            name: name.into(),
+            primitive,
+            min,
+            max,
+            default: 0.0,
+            smoothing: 0.0,
+        }
+    }
+
+    pub fn with_range(mut self, min: f64, max: f64) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn with_default(mut self, default: f64) -> Self {
+        self.default = default;
+        self
+    }
+
+    pub fn with_smoothing(mut self, seconds: f64) -> Self {
+        self.smoothing = seconds;
+        self
+    }
+
+    /// Check that this descriptor makes sense.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("Properties must have a name");
+        }
+
+        if self.min.is_nan() || self.max.is_nan() || self.min > self.max {
+            anyhow::bail!(
+                "Property {}: invalid range {} to {}",
+                self.name,
+                self.min,
+                self.max
+            );
+        }
+
+        if !self.default.is_finite() || self.default < self.min || self.default > self.max {
+            anyhow::bail!(
+                "Property {}: default {} is outside the range {} to {}",
+                self.name,
+                self.default,
+                self.min,
+                self.max
+            );
+        }
+
+        match self.primitive {
+            PrimitiveType::Bool if self.default != 0.0 && self.default != 1.0 => {
+                anyhow::bail!(
+                    "Property {}: bool properties must default to 0 or 1",
+                    self.name
+                );
+            }
+            PrimitiveType::I64 if self.default.fract() != 0.0 => {
+                anyhow::bail!(
+                    "Property {}: integer properties must have an integer default",
+                    self.name
+                );
+            }
+            _ => {}
+        }
+
+        if !self.smoothing.is_finite() || self.smoothing < 0.0 {
+            anyhow::bail!(
+                "Property {}: smoothing must be a non-negative number of seconds",
+                self.name
+            );
+        }
+
+        if self.smoothing > 0.0
+            && !matches!(self.primitive, PrimitiveType::F32 | PrimitiveType::F64)
+        {
+            anyhow::bail!(
+                "Property {}: only float properties may be smoothed",
+                self.name
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        PropertyDescriptor::new("gain", PrimitiveType::F32)
+            .with_range(0.0, 2.0)
+            .with_default(1.0)
+            .with_smoothing(0.01)
+            .validate()
+            .unwrap();
+
+        assert!(PropertyDescriptor::new("gain", PrimitiveType::F32)
+            .with_range(0.0, 2.0)
+            .with_default(3.0)
+            .validate()
+            .is_err());
+        assert!(PropertyDescriptor::new("mode", PrimitiveType::I64)
+            .with_default(0.5)
+            .validate()
+            .is_err());
+        assert!(PropertyDescriptor::new("mode", PrimitiveType::I64)
+            .with_smoothing(0.1)
+            .validate()
+            .is_err());
+        assert!(PropertyDescriptor::new("", PrimitiveType::F32)
+            .validate()
+            .is_err());
+    }
+}
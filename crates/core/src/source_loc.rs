@@ -70,6 +70,8 @@ impl SourceLoc {
                     function,
                     printable_source,
                 });
+            } else {
+                break;
             }
         }
 
@@ -1,11 +1,130 @@
-use crate::VectorDescriptor;
+use crate::{Constant, VectorDescriptor};
 
 /// A state is a writable memory location, usually read with modulus as a delay line.
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct State {
     /// The kind of data this state holds.
     pub vector: VectorDescriptor,
 
     /// The length of this state.
     pub length: u64,
+
+    /// The value every element of the state starts at, or zero if `None`.
+    ///
+    /// This is a constant of the state's primitive, and either of the state's width or of width 1 to fill every lane.
+    /// It is data rather than part of the graph, so backends may let each instance of a program override it, e.g. to
+    /// seed [crate::Op::Noise] differently.  See [crate::Program::set_state_initial].
+    pub initial: Option<Constant>,
+}
+
+impl State {
+    /// The number of bytes needed to store this state.
+    ///
+    /// Saturates at `u64::MAX`, so that absurdly large states are still caught by size limits.
+    pub fn size_in_bytes(&self) -> u64 {
+        self.vector
+            .primitive
+            .size_in_bytes()
+            .saturating_mul(self.vector.width)
+            .saturating_mul(self.length)
+    }
+}
+
+/// Limits on how much memory a program's states may use.
+///
+/// [crate::Program::add_state] enforces the program's limits, and [crate::legalize_for_target] enforces the target's.  The
+/// default is generous, but stops absurdly large states, e.g. from fuzzed programs, from being declared at all.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StateLimits {
+    /// The maximum number of bytes any one state may use, if limited.
+    pub max_state_bytes: Option<u64>,
+
+    /// The maximum number of bytes all states together may use, if limited.
+    pub max_total_state_bytes: Option<u64>,
+}
+
+/// A state which doesn't fit in the [StateLimits].
+#[derive(thiserror::Error, Clone, Debug, Eq, PartialEq)]
+pub enum StateLimitError {
+    #[error("State {state} uses {bytes} bytes, but at most {limit} are allowed per state")]
+    StateTooLarge {
+        state: usize,
+        bytes: u64,
+        limit: u64,
+    },
+
+    /// `bytes` is the total of all states up to and including `state`, the first to go over the limit.
+    #[error(
+        "States up to and including state {state} use {bytes} bytes, but at most {limit} are allowed in total"
+    )]
+    TotalTooLarge {
+        state: usize,
+        bytes: u64,
+        limit: u64,
+    },
+}
+
+impl StateLimitError {
+    /// The index of the state which exceeded the limit.
+    pub fn state(&self) -> usize {
+        match self {
+            StateLimitError::StateTooLarge { state, .. }
+            | StateLimitError::TotalTooLarge { state, .. } => *state,
+        }
+    }
+}
+
+impl StateLimits {
+    /// Limits which allow anything.
+    pub const fn unlimited() -> Self {
+        Self {
+            max_state_bytes: None,
+            max_total_state_bytes: None,
+        }
+    }
+
+    /// Check states against these limits, returning every state which is too large.
+    ///
+    /// The total is reported once, for the first state which takes it over the limit.
+    pub fn check(&self, states: &[State]) -> Vec<StateLimitError> {
+        let mut errors = vec![];
+        let mut total = 0u64;
+        let mut total_reported = false;
+
+        for (i, s) in states.iter().enumerate() {
+            let bytes = s.size_in_bytes();
+            if let Some(limit) = self.max_state_bytes {
+                if bytes > limit {
+                    errors.push(StateLimitError::StateTooLarge {
+                        state: i,
+                        bytes,
+                        limit,
+                    });
+                }
+            }
+
+            total = total.saturating_add(bytes);
+            if let Some(limit) = self.max_total_state_bytes {
+                if total > limit && !total_reported {
+                    errors.push(StateLimitError::TotalTooLarge {
+                        state: i,
+                        bytes: total,
+                        limit,
+                    });
+                    total_reported = true;
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl Default for StateLimits {
+    fn default() -> Self {
+        Self {
+            max_state_bytes: Some(1 << 30),
+            max_total_state_bytes: Some(1 << 32),
+        }
+    }
 }
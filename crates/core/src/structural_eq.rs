@@ -0,0 +1,174 @@
+//! Structural comparison of programs, mostly for testing passes.
+//!
+//! Two programs are structurally equal if they declare the same environment and their graphs are isomorphic, matching
+//! nodes by op and edges by the input they connect to.  Node and edge indices and source locations are ignored, so
+//! a program compares equal to one which built the same graph in a different order.
+use std::collections::HashMap;
+
+use petgraph::algo::is_isomorphic_matching;
+use petgraph::prelude::*;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+
+use crate::*;
+
+impl Program {
+    /// Is this program structurally equal to `other`?  See the module documentation.
+    pub fn structurally_eq(&self, other: &Program) -> bool {
+        self.inputs == other.inputs
+            && self.outputs == other.outputs
+            && self.input_names == other.input_names
+            && self.output_names == other.output_names
+            && self.properties == other.properties
+            && self.buffer_inputs == other.buffer_inputs
+            && self.states == other.states
+            && self.graph.node_count() == other.graph.node_count()
+            && self.graph.edge_count() == other.graph.edge_count()
+            && is_isomorphic_matching(
+                &self.compact_graph(),
+                &other.compact_graph(),
+                |a, b| a == b,
+                |a, b| a == b,
+            )
+    }
+
+    /// Build a compactly indexed copy of the graph for isomorphism checking.
+    ///
+    /// Petgraph's isomorphism doesn't handle multigraphs, but we have them whenever a node feeds more than one input of
+    /// another (e.g. `x * x`).  So parallel edges are merged into one, weighted by the sorted inputs they connect to.
+    fn compact_graph(&self) -> DiGraph<&Op, Vec<usize>> {
+        let mut graph = DiGraph::new();
+        let mut node_map = HashMap::new();
+
+        for n in self.graph.node_indices() {
+            node_map.insert(n, graph.add_node(&self.graph[n].op));
+        }
+
+        let mut edges: HashMap<(NodeIndex, NodeIndex), Vec<usize>> = HashMap::new();
+        for e in self.graph.edge_references() {
+            edges
+                .entry((node_map[&e.source()], node_map[&e.target()]))
+                .or_default()
+                .push(e.weight().input);
+        }
+
+        for ((source, target), mut inputs) in edges {
+            inputs.sort_unstable();
+            graph.add_edge(source, target, inputs);
+        }
+
+        graph
+    }
+}
+
+/// Panic unless the two programs are structurally equal.
+pub fn assert_structurally_eq(left: &Program, right: &Program) {
+    if !left.structurally_eq(right) {
+        panic!(
+            "Programs are not structurally equal\nleft:\n{}\nright:\n{}",
+            left.graphviz(),
+            right.graphviz()
+        );
+    }
+}
+
+/// Run `pass` on a clone of `program`, and panic if it changed anything.
+pub fn assert_pass_makes_no_change<F: FnOnce(&mut Program)>(program: &Program, pass: F) {
+    let mut changed = program.clone();
+    pass(&mut changed);
+    assert_structurally_eq(program, &changed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build `out = x * x + c`, creating nodes and edges in either order.
+    fn build(reversed: bool) -> Program {
+        let mut prog = Program::new();
+        let input = prog.add_input(PrimitiveType::F32, 1).unwrap();
+        let output = prog.add_output(PrimitiveType::F32, 1).unwrap();
+
+        let (read, mul, add, constant, write);
+        if reversed {
+            write = prog.op_write_output_node(output, None).unwrap();
+            constant = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            add = prog.op_add_node(None).unwrap();
+            mul = prog.op_mul_node(None).unwrap();
+            read = prog.op_read_input_node(input, None).unwrap();
+        } else {
+            read = prog.op_read_input_node(input, None).unwrap();
+            mul = prog.op_mul_node(None).unwrap();
+            add = prog.op_add_node(None).unwrap();
+            constant = prog
+                .op_constant_node(Constant::F32(vec![1.0]), None)
+                .unwrap();
+            write = prog.op_write_output_node(output, None).unwrap();
+        }
+
+        let mut edges = vec![
+            (read, mul, 0),
+            (read, mul, 1),
+            (mul, add, 0),
+            (constant, add, 1),
+            (add, write, 0),
+        ];
+        if reversed {
+            edges.reverse();
+        }
+        for (from, to, input) in edges {
+            prog.connect(from, to, input, None).unwrap();
+        }
+
+        prog
+    }
+
+    #[test]
+    fn test_clone_is_equal() {
+        let prog = build(false);
+        assert_structurally_eq(&prog, &prog.clone());
+        assert_structurally_eq(&prog, &build(true));
+    }
+
+    #[test]
+    fn test_differences() {
+        let prog = build(false);
+
+        let mut other = prog.clone();
+        let mul = other
+            .graph
+            .node_indices()
+            .find(|n| other.graph[*n].op == Op::BinOp(BinOp::Mul))
+            .unwrap();
+        other.graph[mul].op = Op::BinOp(BinOp::Sub);
+        assert!(!prog.structurally_eq(&other));
+
+        // Moving an edge to a different input changes the program.
+        let mut other = prog.clone();
+        let edge = other
+            .graph
+            .edge_indices()
+            .find(|e| {
+                let (source, target) = other.graph.edge_endpoints(*e).unwrap();
+                other.graph[source].op.is_constant() && other.graph[target].op.is_bin_op()
+            })
+            .unwrap();
+        other.graph[edge].input = 0;
+        assert!(!prog.structurally_eq(&other));
+
+        let mut other = prog.clone();
+        other.add_state(PrimitiveType::F32, 1, 1).unwrap();
+        assert!(!prog.structurally_eq(&other));
+    }
+
+    #[test]
+    fn test_no_change() {
+        let mut prog = build(false);
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        assert_pass_makes_no_change(&prog, |p| {
+            legalize_for_target(p, &TargetSpec::default(), &mut diags).unwrap();
+        });
+    }
+}
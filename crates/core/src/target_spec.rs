@@ -0,0 +1,67 @@
+//! Describes what a target (usually a backend) can do, so that compilation can be tuned per target.
+use std::borrow::Cow;
+
+use crate::{Op, PrimitiveType, StateLimits};
+
+/// A description of the capabilities of a target.
+///
+/// Backends declare one of these, and passes consult it.  The default is the reference target, which supports
+/// everything and imposes no limits.
+///
+/// Only capabilities which some pass acts on belong here; e.g. lane width and FMA can be added along with the
+/// vectorization and fusion passes that would use them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetSpec {
+    /// Primitives which the target can do math on.
+    pub supported_primitives: Cow<'static, [PrimitiveType]>,
+
+    /// Limits on the memory the program's states may use on this target.
+    pub state_limits: StateLimits,
+
+    /// Restrictions on ops beyond those the ops impose themselves, as pairs of an op and a primitive it may not be
+    /// used with.
+    ///
+    /// Ops are compared exactly, so `(Op::BinOp(BinOp::Div), PrimitiveType::I64)` denies integer division but leaves
+    /// the other binary operations alone.  A node is rejected if its output or its inputs are of a denied primitive.
+    pub denied_op_primitives: Cow<'static, [(Op, PrimitiveType)]>,
+}
+
+impl TargetSpec {
+    /// A target which does no f64 math.
+    pub fn f32_only() -> Self {
+        Self {
+            supported_primitives: Cow::Borrowed(&[
+                PrimitiveType::Bool,
+                PrimitiveType::I64,
+                PrimitiveType::F32,
+            ]),
+            ..Default::default()
+        }
+    }
+
+    pub fn supports_primitive(&self, primitive: PrimitiveType) -> bool {
+        self.supported_primitives.contains(&primitive)
+    }
+
+    /// Does this target deny using `op` with `primitive`?  See [TargetSpec::denied_op_primitives].
+    pub fn denies_op_primitive(&self, op: &Op, primitive: PrimitiveType) -> bool {
+        self.denied_op_primitives
+            .iter()
+            .any(|(o, p)| o == op && *p == primitive)
+    }
+}
+
+impl Default for TargetSpec {
+    fn default() -> Self {
+        Self {
+            supported_primitives: Cow::Borrowed(&[
+                PrimitiveType::Bool,
+                PrimitiveType::I64,
+                PrimitiveType::F32,
+                PrimitiveType::F64,
+            ]),
+            state_limits: StateLimits::unlimited(),
+            denied_op_primitives: Cow::Borrowed(&[]),
+        }
+    }
+}
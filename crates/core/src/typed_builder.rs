@@ -0,0 +1,578 @@
+//! A typed layer over [Program] for building graphs from Rust.
+//!
+//! The graph itself is dynamically typed, and mistakes are only caught by type inference.  When building programs
+//! from Rust, we can do better: [Signal] carries its primitive and width in the type, so mismatches fail to compile
+//! instead.  Everything erases to plain nodes in the underlying [Program].
+//!
+//! For example, this doesn't compile, because the widths differ:
+//!
+//! ```compile_fail
+//! use waveling_core::*;
+//!
+//! let mut program = Program::new();
+//! let mut builder = TypedBuilder::new(&mut program);
+//! let a = builder.constant::<f32, 2>([1.0, 2.0], None).unwrap();
+//! let b = builder.constant::<f32, 3>([1.0, 2.0, 3.0], None).unwrap();
+//! builder.add(a, b, None).unwrap();
+//! ```
+use std::marker::PhantomData;
+
+use anyhow::Result;
+
+use crate::*;
+
+/// A Rust type which corresponds to one of our primitives.
+pub trait Primitive: Copy + 'static {
+    const PRIMITIVE: PrimitiveType;
+
+    /// Build a constant from a slice of this type.
+    fn to_constant(vals: &[Self]) -> Constant;
+}
+
+/// Primitives which support arithmetic.
+pub trait Numeric: Primitive {}
+
+macro_rules! decl_primitive {
+    ($ty: ty, $variant: ident) => {
+        impl Primitive for $ty {
+            const PRIMITIVE: PrimitiveType = PrimitiveType::$variant;
+
+            fn to_constant(vals: &[Self]) -> Constant {
+                Constant::$variant(vals.to_vec())
+            }
+        }
+    };
+}
+
+decl_primitive!(bool, Bool);
+decl_primitive!(i64, I64);
+decl_primitive!(f32, F32);
+decl_primitive!(f64, F64);
+
+/// Primitives which are floating point.
+pub trait Float: Numeric {}
+
+impl Numeric for i64 {}
+impl Numeric for f32 {}
+impl Numeric for f64 {}
+
+impl Float for f32 {}
+impl Float for f64 {}
+
+/// The output of a node, which is a vector of `N` elements of `P`.
+#[derive(Debug)]
+pub struct Signal<P: Primitive, const N: usize> {
+    node: OperationGraphNode,
+    _phantom: PhantomData<P>,
+}
+
+// Derives would put bounds on `P`, which we don't want.
+impl<P: Primitive, const N: usize> Clone for Signal<P, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: Primitive, const N: usize> Copy for Signal<P, N> {}
+
+impl<P: Primitive, const N: usize> Signal<P, N> {
+    fn new(node: OperationGraphNode) -> Self {
+        Self {
+            node,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Get the underlying node.
+    pub fn node(&self) -> OperationGraphNode {
+        self.node
+    }
+}
+
+/// An input of the program, which may be read to get a [Signal].
+#[derive(Debug)]
+pub struct TypedInput<P: Primitive, const N: usize> {
+    index: usize,
+    _phantom: PhantomData<P>,
+}
+
+/// An output of the program, which may be written with a [Signal] of the same type.
+#[derive(Debug)]
+pub struct TypedOutput<P: Primitive, const N: usize> {
+    index: usize,
+    _phantom: PhantomData<P>,
+}
+
+/// A property of the program.  Properties are always scalars.
+#[derive(Debug)]
+pub struct TypedProperty<P: Primitive> {
+    index: usize,
+    _phantom: PhantomData<P>,
+}
+
+/// A state of the program, used for feedback.
+#[derive(Debug)]
+pub struct TypedState<P: Primitive, const N: usize> {
+    index: usize,
+    _phantom: PhantomData<P>,
+}
+
+impl<P: Primitive, const N: usize> TypedInput<P, N> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<P: Primitive, const N: usize> TypedOutput<P, N> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<P: Primitive> TypedProperty<P> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<P: Primitive, const N: usize> TypedState<P, N> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// Build a [Program] with widths and primitives checked by the Rust compiler.
+pub struct TypedBuilder<'a> {
+    program: &'a mut Program,
+}
+
+macro_rules! decl_typed_binop {
+    ($name: ident, $method: ident) => {
+        pub fn $name<P: Numeric, const N: usize>(
+            &mut self,
+            left: Signal<P, N>,
+            right: Signal<P, N>,
+            source_loc: Option<SourceLoc>,
+        ) -> Result<Signal<P, N>> {
+            let node = self.program.$method(source_loc.clone())?;
+            self.program
+                .connect(left.node, node, 0, source_loc.clone())?;
+            self.program.connect(right.node, node, 1, source_loc)?;
+            Ok(Signal::new(node))
+        }
+    };
+}
+
+/// Declare a unary operation which preserves its input's type.
+macro_rules! decl_typed_unop {
+    ($name: ident, $method: ident, $bound: ident) => {
+        pub fn $name<P: $bound, const N: usize>(
+            &mut self,
+            value: Signal<P, N>,
+            source_loc: Option<SourceLoc>,
+        ) -> Result<Signal<P, N>> {
+            let node = self.program.$method(source_loc.clone())?;
+            self.program.connect(value.node, node, 0, source_loc)?;
+            Ok(Signal::new(node))
+        }
+    };
+}
+
+/// Declare a comparison, which produces bools.
+macro_rules! decl_typed_compare {
+    ($name: ident, $method: ident, $bound: ident) => {
+        pub fn $name<P: $bound, const N: usize>(
+            &mut self,
+            left: Signal<P, N>,
+            right: Signal<P, N>,
+            source_loc: Option<SourceLoc>,
+        ) -> Result<Signal<bool, N>> {
+            let node = self.program.$method(source_loc.clone())?;
+            self.program
+                .connect(left.node, node, 0, source_loc.clone())?;
+            self.program.connect(right.node, node, 1, source_loc)?;
+            Ok(Signal::new(node))
+        }
+    };
+}
+
+/// Declare a binary operation which only works on one primitive.
+macro_rules! decl_typed_binop_of {
+    ($name: ident, $method: ident, $prim: ty) => {
+        pub fn $name<const N: usize>(
+            &mut self,
+            left: Signal<$prim, N>,
+            right: Signal<$prim, N>,
+            source_loc: Option<SourceLoc>,
+        ) -> Result<Signal<$prim, N>> {
+            let node = self.program.$method(source_loc.clone())?;
+            self.program
+                .connect(left.node, node, 0, source_loc.clone())?;
+            self.program.connect(right.node, node, 1, source_loc)?;
+            Ok(Signal::new(node))
+        }
+    };
+}
+
+impl<'a> TypedBuilder<'a> {
+    pub fn new(program: &'a mut Program) -> Self {
+        Self { program }
+    }
+
+    /// Get the underlying program, e.g. to build parts of the graph which this layer doesn't cover.
+    pub fn program(&mut self) -> &mut Program {
+        self.program
+    }
+
+    pub fn add_input<P: Primitive, const N: usize>(&mut self) -> Result<TypedInput<P, N>> {
+        let index = self.program.add_input(P::PRIMITIVE, N as u64)?;
+        Ok(TypedInput {
+            index,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn add_output<P: Primitive, const N: usize>(&mut self) -> Result<TypedOutput<P, N>> {
+        let index = self.program.add_output(P::PRIMITIVE, N as u64)?;
+        Ok(TypedOutput {
+            index,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Add a property.  The descriptor's primitive must match `P`.
+    pub fn add_property<P: Primitive>(
+        &mut self,
+        descriptor: PropertyDescriptor,
+    ) -> Result<TypedProperty<P>> {
+        if descriptor.primitive != P::PRIMITIVE {
+            anyhow::bail!(
+                "Property {} is declared as {}, but was added as {}",
+                descriptor.name,
+                descriptor.primitive,
+                P::PRIMITIVE
+            );
+        }
+
+        let index = self.program.add_property(descriptor)?;
+        Ok(TypedProperty {
+            index,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn add_state<P: Primitive, const N: usize>(
+        &mut self,
+        length: u64,
+    ) -> Result<TypedState<P, N>> {
+        let index = self.program.add_state(P::PRIMITIVE, N as u64, length)?;
+        Ok(TypedState {
+            index,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn read_input<P: Primitive, const N: usize>(
+        &mut self,
+        input: &TypedInput<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        Ok(Signal::new(
+            self.program.op_read_input_node(input.index, source_loc)?,
+        ))
+    }
+
+    pub fn read_property<P: Primitive>(
+        &mut self,
+        property: &TypedProperty<P>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, 1>> {
+        Ok(Signal::new(
+            self.program
+                .op_read_property_node(property.index, source_loc)?,
+        ))
+    }
+
+    pub fn write_output<P: Primitive, const N: usize>(
+        &mut self,
+        output: &TypedOutput<P, N>,
+        value: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<()> {
+        let node = self
+            .program
+            .op_write_output_node(output.index, source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)
+    }
+
+    /// Read the value written to a state by [TypedBuilder::feedback_write] on the previous sample.
+    pub fn feedback_read<P: Primitive, const N: usize>(
+        &mut self,
+        state: &TypedState<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        Ok(Signal::new(
+            self.program
+                .op_feedback_read_node(state.index, source_loc)?,
+        ))
+    }
+
+    pub fn feedback_write<P: Primitive, const N: usize>(
+        &mut self,
+        state: &TypedState<P, N>,
+        value: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<()> {
+        let node = self
+            .program
+            .op_feedback_write_node(state.index, source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)
+    }
+
+    /// A phase accumulator running at `frequency` Hz, keeping its phase in `state`.
+    pub fn phasor<P: Float, const N: usize>(
+        &mut self,
+        state: &TypedState<P, N>,
+        frequency: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        let node = self
+            .program
+            .op_phasor_node(state.index, source_loc.clone())?;
+        self.program.connect(frequency.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// A sine oscillator running at `frequency` Hz, keeping its phase in `state`.
+    pub fn sin_osc<P: Float, const N: usize>(
+        &mut self,
+        state: &TypedState<P, N>,
+        frequency: Signal<P, N>,
+        phase_offset: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        let node = self
+            .program
+            .op_sin_osc_node(state.index, source_loc.clone())?;
+        self.program
+            .connect(frequency.node, node, 0, source_loc.clone())?;
+        self.program
+            .connect(phase_offset.node, node, 1, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Deterministic noise, as uniformly distributed i64s, keeping the generator's counters in `state`.
+    pub fn noise<const N: usize>(
+        &mut self,
+        state: &TypedState<i64, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<i64, N>> {
+        Ok(Signal::new(
+            self.program.op_noise_node(state.index, source_loc)?,
+        ))
+    }
+
+    pub fn constant<P: Primitive, const N: usize>(
+        &mut self,
+        vals: [P; N],
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        Ok(Signal::new(
+            self.program
+                .op_constant_node(P::to_constant(&vals[..]), source_loc)?,
+        ))
+    }
+
+    pub fn clock(&mut self, source_loc: Option<SourceLoc>) -> Result<Signal<i64, 1>> {
+        Ok(Signal::new(self.program.op_clock_node(source_loc)?))
+    }
+
+    pub fn sr(&mut self, source_loc: Option<SourceLoc>) -> Result<Signal<i64, 1>> {
+        Ok(Signal::new(self.program.op_sr_node(source_loc)?))
+    }
+
+    /// Broadcast a scalar to a vector.
+    ///
+    /// The graph broadcasts implicitly, so this adds no nodes; it only changes the type on the Rust side.
+    pub fn broadcast<P: Primitive, const N: usize>(&mut self, value: Signal<P, 1>) -> Signal<P, N> {
+        Signal::new(value.node)
+    }
+
+    decl_typed_binop!(add, op_add_node);
+    decl_typed_binop!(sub, op_sub_node);
+    decl_typed_binop!(mul, op_mul_node);
+    decl_typed_binop!(div, op_div_node);
+
+    decl_typed_binop_of!(logical_and, op_logical_and_node, bool);
+    decl_typed_binop_of!(logical_or, op_logical_or_node, bool);
+    decl_typed_binop_of!(bit_and, op_bit_and_node, i64);
+    decl_typed_binop_of!(bit_or, op_bit_or_node, i64);
+    decl_typed_binop_of!(bit_xor, op_bit_xor_node, i64);
+    decl_typed_binop_of!(shl, op_shl_node, i64);
+    decl_typed_binop_of!(shr, op_shr_node, i64);
+
+    pub fn logical_not<const N: usize>(
+        &mut self,
+        value: Signal<bool, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<bool, N>> {
+        let node = self.program.op_logical_not_node(source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    decl_typed_unop!(sqrt, op_sqrt_node, Float);
+    decl_typed_unop!(exp, op_exp_node, Float);
+    decl_typed_unop!(log, op_log_node, Float);
+    decl_typed_unop!(abs, op_abs_node, Numeric);
+    decl_typed_unop!(floor, op_floor_node, Float);
+    decl_typed_unop!(ceil, op_ceil_node, Float);
+    decl_typed_unop!(round, op_round_node, Float);
+    decl_typed_unop!(trunc, op_trunc_node, Float);
+    decl_typed_unop!(fract, op_fract_node, Float);
+
+    decl_typed_compare!(eq, op_eq_node, Primitive);
+    decl_typed_compare!(ne, op_ne_node, Primitive);
+    decl_typed_compare!(lt, op_lt_node, Numeric);
+    decl_typed_compare!(le, op_le_node, Numeric);
+    decl_typed_compare!(gt, op_gt_node, Numeric);
+    decl_typed_compare!(ge, op_ge_node, Numeric);
+
+    /// Choose `on_true` where `cond` is true and `on_false` elsewhere.
+    pub fn select<P: Primitive, const N: usize>(
+        &mut self,
+        cond: Signal<bool, N>,
+        on_true: Signal<P, N>,
+        on_false: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        let node = self.program.op_select_node(source_loc.clone())?;
+        self.program
+            .connect(cond.node, node, 0, source_loc.clone())?;
+        self.program
+            .connect(on_true.node, node, 1, source_loc.clone())?;
+        self.program.connect(on_false.node, node, 2, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Interpolate from `a` to `b` by `t`.
+    pub fn lerp<P: Float, const N: usize>(
+        &mut self,
+        a: Signal<P, N>,
+        b: Signal<P, N>,
+        t: Signal<P, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, N>> {
+        let node = self.program.op_lerp_node(source_loc.clone())?;
+        self.program.connect(a.node, node, 0, source_loc.clone())?;
+        self.program.connect(b.node, node, 1, source_loc.clone())?;
+        self.program.connect(t.node, node, 2, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Encode a stereo pair of left and right into mid and side.
+    pub fn ms_encode<P: Float>(
+        &mut self,
+        stereo: Signal<P, 2>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, 2>> {
+        let node = self.program.op_ms_encode_node(source_loc.clone())?;
+        self.program.connect(stereo.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Decode mid and side back into a stereo pair of left and right.
+    pub fn ms_decode<P: Float>(
+        &mut self,
+        mid_side: Signal<P, 2>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, 2>> {
+        let node = self.program.op_ms_decode_node(source_loc.clone())?;
+        self.program.connect(mid_side.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Change the width of a stereo pair; see [Op::StereoWidth].
+    pub fn stereo_width<P: Float>(
+        &mut self,
+        stereo: Signal<P, 2>,
+        width: Signal<P, 1>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<P, 2>> {
+        let node = self.program.op_stereo_width_node(source_loc.clone())?;
+        self.program
+            .connect(stereo.node, node, 0, source_loc.clone())?;
+        self.program.connect(width.node, node, 1, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    pub fn bit_not<const N: usize>(
+        &mut self,
+        value: Signal<i64, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<i64, N>> {
+        let node = self.program.op_bit_not_node(source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    decl_typed_unop!(negate, op_negate_node, Numeric);
+
+    pub fn cast<To: Primitive, From: Primitive, const N: usize>(
+        &mut self,
+        value: Signal<From, N>,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<To, N>> {
+        let node = self
+            .program
+            .op_cast_node(To::PRIMITIVE, source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+
+    /// Convert floats to integers with an explicit rounding mode.
+    pub fn float_to_int<P: Float, const N: usize>(
+        &mut self,
+        value: Signal<P, N>,
+        mode: RoundingMode,
+        source_loc: Option<SourceLoc>,
+    ) -> Result<Signal<i64, N>> {
+        let node = self
+            .program
+            .op_float_to_int_node(mode, source_loc.clone())?;
+        self.program.connect(value.node, node, 0, source_loc)?;
+        Ok(Signal::new(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_stereo_gain() {
+        let mut program = Program::new();
+        let mut builder = TypedBuilder::new(&mut program);
+
+        let input = builder.add_input::<f32, 2>().unwrap();
+        let output = builder.add_output::<f64, 2>().unwrap();
+        let gain = builder
+            .add_property::<f32>(
+                PropertyDescriptor::new("gain", PrimitiveType::F32).with_default(1.0),
+            )
+            .unwrap();
+
+        let samples = builder.read_input(&input, None).unwrap();
+        let gain = builder.read_property(&gain, None).unwrap();
+        let gain = builder.broadcast::<f32, 2>(gain);
+        let scaled = builder.mul(samples, gain, None).unwrap();
+        let widened = builder.cast::<f64, _, 2>(scaled, None).unwrap();
+        builder.write_output(&output, widened, None).unwrap();
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut program, &mut diags).unwrap();
+        let types = type_inference(&program, &mut diags).unwrap();
+        assert_eq!(types.get_type(scaled.node()), Some(DataType::new_v_f32(2)));
+        assert_eq!(types.get_type(widened.node()), Some(DataType::new_v_f64(2)));
+    }
+}
@@ -3,7 +3,9 @@
 //! Vectors are the results of nodes and a single frame stored in a state.
 use std::fmt::Display;
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Copy, Clone, Hash, strum::Display)]
+#[derive(
+    Debug, Eq, Ord, PartialEq, PartialOrd, Copy, Clone, Hash, strum::Display, strum::EnumString,
+)]
 #[strum(serialize_all = "snake_case")]
 pub enum PrimitiveType {
     Bool,
@@ -16,48 +18,97 @@ pub enum PrimitiveType {
     F64,
 }
 
+impl PrimitiveType {
+    /// The size of one element of this primitive in memory.
+    pub fn size_in_bytes(&self) -> u64 {
+        match self {
+            PrimitiveType::Bool => 1,
+            PrimitiveType::I64 => 8,
+            PrimitiveType::F32 => 4,
+            PrimitiveType::F64 => 8,
+        }
+    }
+}
+
+/// How often a value may change.
+///
+/// Rates are ordered from slowest to fastest, so combining values gives the maximum of their rates.  A control-rate
+/// value used where an audio-rate one is expected is upsampled by holding it for the whole block.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Hash,
+    strum::Display,
+    derive_more::IsVariant,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum Rate {
+    /// The value changes at most once per block, e.g. properties.  Backends may compute these outside the per-sample
+    /// loop.
+    Control,
+
+    /// The value may change every sample.
+    #[default]
+    Audio,
+}
+
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct VectorDescriptor {
     pub primitive: PrimitiveType,
     pub width: u64,
+    pub rate: Rate,
 }
 
 impl VectorDescriptor {
+    /// An audio-rate vector.
     pub fn new(primitive: PrimitiveType, width: u64) -> VectorDescriptor {
-        VectorDescriptor { primitive, width }
+        VectorDescriptor {
+            primitive,
+            width,
+            rate: Rate::Audio,
+        }
     }
 
     pub fn new_bool(width: u64) -> Self {
-        Self {
-            primitive: PrimitiveType::Bool,
-            width,
-        }
+        Self::new(PrimitiveType::Bool, width)
     }
 
     pub fn new_i64(width: u64) -> Self {
-        Self {
-            primitive: PrimitiveType::I64,
-            width,
-        }
+        Self::new(PrimitiveType::I64, width)
     }
 
     pub fn new_f32(width: u64) -> Self {
-        Self {
-            primitive: PrimitiveType::F32,
-            width,
-        }
+        Self::new(PrimitiveType::F32, width)
     }
 
     pub fn new_f64(width: u64) -> Self {
-        Self {
-            primitive: PrimitiveType::F64,
-            width,
-        }
+        Self::new(PrimitiveType::F64, width)
+    }
+
+    pub fn with_rate(self, rate: Rate) -> Self {
+        Self { rate, ..self }
+    }
+
+    /// Can a value of type `value` be stored somewhere of this type, e.g. written to an output?
+    ///
+    /// The primitive and width must match, and the value must not be faster than the destination.
+    pub fn accepts(&self, value: &VectorDescriptor) -> bool {
+        self.primitive == value.primitive && self.width == value.width && value.rate <= self.rate
     }
 }
 
 impl Display for VectorDescriptor {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rate.is_control() {
+            write!(formatter, "control ")?;
+        }
+
         if self.width == 1 {
             write!(formatter, "{}", self.primitive)?;
         } else {
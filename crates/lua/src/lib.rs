@@ -0,0 +1,621 @@
+//! Build [Program]s from Lua scripts.
+//!
+//! Scripts get a global `waveling` table with functions for declaring the program's environment and building its graph.
+//! Every node and edge records the Lua stack at the point it was made, so diagnostics point at the script:
+//!
+//! ```lua
+//! local x = waveling.input("f32", 2)
+//! local gain = waveling.property("gain", "f32", { min = 0, max = 2, default = 1 })
+//! local out = waveling.output("f32", 2)
+//! waveling.connect(waveling.mul(x, gain), out, 0)
+//! ```
+//!
+//! - `input(type, width, name)` declares an input and returns a node reading it.  The name is optional.
+//! - `output(type, width, name)` declares an output and returns the node writing it, which must then be connected to.
+//!   The name is optional.
+//! - `buffer_input(type, width, length)` declares a buffer input, e.g. a wavetable, and returns its index.
+//! - `read_buffer(buffer, index)` reads the frame of a buffer input at the i64 `index`, wrapping around its length.
+//! - `property(name, type, options)` declares a property and returns a node reading it.  `options` is optional, and may
+//!   set `min`, `max`, `default` and `smoothing` (in seconds).
+//! - `state(type, width, length)` declares a state and returns its index.  `state_initial(state, values...)` sets the
+//!   value it starts at, which for noise is the seed.
+//! - `const(type, values...)` makes a constant of the given values.
+//! - `clock()` and `sr()` read the clock and the sample rate.
+//! - `add`, `sub`, `mul`, `div`, `logical_and`, `logical_or`, `bit_and`, `bit_or`, `bit_xor`, `shl` and `shr` take two
+//!   nodes and return a node combining them.  So do the comparisons `eq`, `ne`, `lt`, `le`, `gt` and `ge`.
+//! - `negate`, `logical_not`, `bit_not`, `sqrt`, `exp`, `log`, `abs`, `floor`, `ceil`, `round`, `trunc` and `fract`
+//!   take one node.
+//! - `select(cond, if_true, if_false)` chooses per element, and `lerp(a, b, t)` interpolates from `a` to `b`.
+//! - `cast(type, value)` casts to another primitive, and `float_to_int(mode, value)` rounds floats to i64s with the
+//!   mode `"trunc"`, `"floor"` or `"nearest_even"`.
+//! - `ms_encode` and `ms_decode` take a stereo pair, and `stereo_width(stereo, width)` changes its width.
+//! - `feedback_read(state)` reads the value `feedback_write(state, value)` wrote on the previous sample.
+//! - `phasor(state, freq)`, `sin_osc(state, freq, offset)` and `noise(state)` are generators keeping their own state.
+//! - `connect(from, to, input)` connects `from` to the given input of `to`.
+//!
+//! Types are given as strings: `"bool"`, `"i64"`, `"f32"` or `"f64"`.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Result;
+use mlua::{FromLua, Lua, UserData, Variadic};
+
+use waveling_core::*;
+
+/// A node of the program being built, as seen from Lua.
+#[derive(Copy, Clone, Debug)]
+struct LuaNode(OperationGraphNode);
+
+impl UserData for LuaNode {}
+
+type SharedProgram = Rc<RefCell<Program>>;
+
+type NodeCtor = fn(&mut Program, Option<SourceLoc>) -> Result<OperationGraphNode>;
+
+fn parse_primitive(name: &str) -> mlua::Result<PrimitiveType> {
+    name.parse()
+        .map_err(|_| mlua::Error::RuntimeError(format!("Unknown primitive type {}", name)))
+}
+
+fn parse_rounding_mode(name: &str) -> mlua::Result<RoundingMode> {
+    match name {
+        "trunc" => Ok(RoundingMode::Trunc),
+        "floor" => Ok(RoundingMode::Floor),
+        "nearest_even" => Ok(RoundingMode::NearestEven),
+        _ => Err(mlua::Error::RuntimeError(format!(
+            "Unknown rounding mode {}",
+            name
+        ))),
+    }
+}
+
+fn collect_values<'lua, T: FromLua<'lua>>(
+    lua: &'lua Lua,
+    values: Variadic<mlua::Value<'lua>>,
+) -> mlua::Result<Vec<T>> {
+    values.into_iter().map(|v| lua.unpack(v)).collect()
+}
+
+/// Build a constant of the given primitive from Lua values, which must not be empty.
+fn build_constant<'lua>(
+    lua: &'lua Lua,
+    primitive: PrimitiveType,
+    values: Variadic<mlua::Value<'lua>>,
+) -> mlua::Result<Constant> {
+    let constant = match primitive {
+        PrimitiveType::Bool => Constant::Bool(collect_values(lua, values)?),
+        PrimitiveType::I64 => Constant::I64(collect_values(lua, values)?),
+        PrimitiveType::F32 => Constant::F32(collect_values(lua, values)?),
+        PrimitiveType::F64 => Constant::F64(collect_values(lua, values)?),
+    };
+    if constant.width() == 0 {
+        return Err(mlua::Error::RuntimeError(
+            "Constants must have at least one value".to_string(),
+        ));
+    }
+
+    Ok(constant)
+}
+
+/// Build a node which takes its inputs, in order, from `inputs`.
+fn build_op(
+    lua: &Lua,
+    program: &SharedProgram,
+    ctor: impl FnOnce(&mut Program, Option<SourceLoc>) -> Result<OperationGraphNode>,
+    inputs: &[LuaNode],
+) -> mlua::Result<LuaNode> {
+    let source_loc = SourceLoc::from_lua(lua);
+    let mut program = program.borrow_mut();
+
+    let node = ctor(&mut program, Some(source_loc.clone())).map_err(mlua::Error::external)?;
+    for (i, input) in inputs.iter().enumerate() {
+        program
+            .connect(input.0, node, i, Some(source_loc.clone()))
+            .map_err(mlua::Error::external)?;
+    }
+
+    Ok(LuaNode(node))
+}
+
+fn build_module<'lua>(lua: &'lua Lua, program: &SharedProgram) -> mlua::Result<mlua::Table<'lua>> {
+    let module = lua.create_table()?;
+
+    let p = program.clone();
+    module.set(
+        "input",
+        lua.create_function(
+            move |lua, (ty, width, name): (String, u64, Option<String>)| {
+                let source_loc = SourceLoc::from_lua(lua);
+                let primitive = parse_primitive(&ty)?;
+                let mut program = p.borrow_mut();
+                let index = match name {
+                    Some(n) => program.add_named_input(n, primitive, width),
+                    None => program.add_input(primitive, width),
+                }
+                .map_err(mlua::Error::external)?;
+                let node = program
+                    .op_read_input_node(index, Some(source_loc))
+                    .map_err(mlua::Error::external)?;
+                Ok(LuaNode(node))
+            },
+        )?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "output",
+        lua.create_function(
+            move |lua, (ty, width, name): (String, u64, Option<String>)| {
+                let source_loc = SourceLoc::from_lua(lua);
+                let primitive = parse_primitive(&ty)?;
+                let mut program = p.borrow_mut();
+                let index = match name {
+                    Some(n) => program.add_named_output(n, primitive, width),
+                    None => program.add_output(primitive, width),
+                }
+                .map_err(mlua::Error::external)?;
+                let node = program
+                    .op_write_output_node(index, Some(source_loc))
+                    .map_err(mlua::Error::external)?;
+                Ok(LuaNode(node))
+            },
+        )?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "property",
+        lua.create_function(
+            move |lua, (name, ty, options): (String, String, Option<mlua::Table>)| {
+                let source_loc = SourceLoc::from_lua(lua);
+                let mut descriptor = PropertyDescriptor::new(name, parse_primitive(&ty)?);
+                if let Some(options) = options {
+                    let min = options
+                        .get::<_, Option<f64>>("min")?
+                        .unwrap_or(descriptor.min);
+                    let max = options
+                        .get::<_, Option<f64>>("max")?
+                        .unwrap_or(descriptor.max);
+                    descriptor = descriptor.with_range(min, max);
+                    if let Some(default) = options.get::<_, Option<f64>>("default")? {
+                        descriptor = descriptor.with_default(default);
+                    }
+                    if let Some(smoothing) = options.get::<_, Option<f64>>("smoothing")? {
+                        descriptor = descriptor.with_smoothing(smoothing);
+                    }
+                }
+
+                let mut program = p.borrow_mut();
+                let index = program
+                    .add_property(descriptor)
+                    .map_err(mlua::Error::external)?;
+                let node = program
+                    .op_read_property_node(index, Some(source_loc))
+                    .map_err(mlua::Error::external)?;
+                Ok(LuaNode(node))
+            },
+        )?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "buffer_input",
+        lua.create_function(move |_, (ty, width, length): (String, u64, u64)| {
+            p.borrow_mut()
+                .add_buffer_input(parse_primitive(&ty)?, width, length)
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "read_buffer",
+        lua.create_function(move |lua, (buffer, index): (usize, LuaNode)| {
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_read_input_indexed_node(buffer, loc),
+                &[index],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "const",
+        lua.create_function(move |lua, (ty, values): (String, Variadic<mlua::Value>)| {
+            let constant = build_constant(lua, parse_primitive(&ty)?, values)?;
+            let node = p
+                .borrow_mut()
+                .op_constant_node(constant, Some(SourceLoc::from_lua(lua)))
+                .map_err(mlua::Error::external)?;
+            Ok(LuaNode(node))
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "state",
+        lua.create_function(move |_, (ty, width, length): (String, u64, u64)| {
+            p.borrow_mut()
+                .add_state(parse_primitive(&ty)?, width, length)
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "state_initial",
+        lua.create_function(
+            move |lua, (state, values): (usize, Variadic<mlua::Value>)| {
+                let mut program = p.borrow_mut();
+                let primitive = program
+                    .states
+                    .get(state)
+                    .map(|s| s.vector.primitive)
+                    .ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("State {} does not exist", state))
+                    })?;
+                let initial = build_constant(lua, primitive, values)?;
+                program
+                    .set_state_initial(state, initial)
+                    .map_err(mlua::Error::external)
+            },
+        )?,
+    )?;
+
+    let nullary: [(&str, NodeCtor); 2] = [
+        ("clock", Program::op_clock_node),
+        ("sr", Program::op_sr_node),
+    ];
+    for (name, ctor) in nullary {
+        let p = program.clone();
+        module.set(
+            name,
+            lua.create_function(move |lua, ()| build_op(lua, &p, ctor, &[]))?,
+        )?;
+    }
+
+    let binops: [(&str, NodeCtor); 17] = [
+        ("add", Program::op_add_node),
+        ("sub", Program::op_sub_node),
+        ("mul", Program::op_mul_node),
+        ("div", Program::op_div_node),
+        ("logical_and", Program::op_logical_and_node),
+        ("logical_or", Program::op_logical_or_node),
+        ("bit_and", Program::op_bit_and_node),
+        ("bit_or", Program::op_bit_or_node),
+        ("bit_xor", Program::op_bit_xor_node),
+        ("shl", Program::op_shl_node),
+        ("shr", Program::op_shr_node),
+        ("eq", Program::op_eq_node),
+        ("ne", Program::op_ne_node),
+        ("lt", Program::op_lt_node),
+        ("le", Program::op_le_node),
+        ("gt", Program::op_gt_node),
+        ("ge", Program::op_ge_node),
+    ];
+    for (name, ctor) in binops {
+        let p = program.clone();
+        module.set(
+            name,
+            lua.create_function(move |lua, (left, right): (LuaNode, LuaNode)| {
+                build_op(lua, &p, ctor, &[left, right])
+            })?,
+        )?;
+    }
+
+    let unops: [(&str, NodeCtor); 14] = [
+        ("negate", Program::op_negate_node),
+        ("logical_not", Program::op_logical_not_node),
+        ("bit_not", Program::op_bit_not_node),
+        ("sqrt", Program::op_sqrt_node),
+        ("exp", Program::op_exp_node),
+        ("log", Program::op_log_node),
+        ("abs", Program::op_abs_node),
+        ("floor", Program::op_floor_node),
+        ("ceil", Program::op_ceil_node),
+        ("round", Program::op_round_node),
+        ("trunc", Program::op_trunc_node),
+        ("fract", Program::op_fract_node),
+        ("ms_encode", Program::op_ms_encode_node),
+        ("ms_decode", Program::op_ms_decode_node),
+    ];
+    for (name, ctor) in unops {
+        let p = program.clone();
+        module.set(
+            name,
+            lua.create_function(move |lua, value: LuaNode| build_op(lua, &p, ctor, &[value]))?,
+        )?;
+    }
+
+    let ternaries: [(&str, NodeCtor); 2] = [
+        ("select", Program::op_select_node),
+        ("lerp", Program::op_lerp_node),
+    ];
+    for (name, ctor) in ternaries {
+        let p = program.clone();
+        module.set(
+            name,
+            lua.create_function(move |lua, (a, b, c): (LuaNode, LuaNode, LuaNode)| {
+                build_op(lua, &p, ctor, &[a, b, c])
+            })?,
+        )?;
+    }
+
+    let p = program.clone();
+    module.set(
+        "cast",
+        lua.create_function(move |lua, (ty, value): (String, LuaNode)| {
+            let primitive = parse_primitive(&ty)?;
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_cast_node(primitive, loc),
+                &[value],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "float_to_int",
+        lua.create_function(move |lua, (mode, value): (String, LuaNode)| {
+            let mode = parse_rounding_mode(&mode)?;
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_float_to_int_node(mode, loc),
+                &[value],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "feedback_read",
+        lua.create_function(move |lua, state: usize| {
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_feedback_read_node(state, loc),
+                &[],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "feedback_write",
+        lua.create_function(move |lua, (state, value): (usize, LuaNode)| {
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_feedback_write_node(state, loc),
+                &[value],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "phasor",
+        lua.create_function(move |lua, (state, freq): (usize, LuaNode)| {
+            build_op(
+                lua,
+                &p,
+                |prog, loc| prog.op_phasor_node(state, loc),
+                &[freq],
+            )
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "sin_osc",
+        lua.create_function(
+            move |lua, (state, freq, offset): (usize, LuaNode, LuaNode)| {
+                build_op(
+                    lua,
+                    &p,
+                    |prog, loc| prog.op_sin_osc_node(state, loc),
+                    &[freq, offset],
+                )
+            },
+        )?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "noise",
+        lua.create_function(move |lua, state: usize| {
+            build_op(lua, &p, |prog, loc| prog.op_noise_node(state, loc), &[])
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "stereo_width",
+        lua.create_function(move |lua, (stereo, width): (LuaNode, LuaNode)| {
+            build_op(lua, &p, Program::op_stereo_width_node, &[stereo, width])
+        })?,
+    )?;
+
+    let p = program.clone();
+    module.set(
+        "connect",
+        lua.create_function(move |lua, (from, to, input): (LuaNode, LuaNode, usize)| {
+            p.borrow_mut()
+                .connect(from.0, to.0, input, Some(SourceLoc::from_lua(lua)))
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    Ok(module)
+}
+
+/// Run a Lua script, returning the program it built.
+///
+/// `chunk_name` is used as the file name in source locations.  The program is returned as built; running passes over
+/// it, and so finding out whether it is valid, is up to the caller.
+pub fn program_from_lua(source: &str, chunk_name: &str) -> Result<Program> {
+    let program: SharedProgram = Rc::new(RefCell::new(Program::new()));
+
+    {
+        let lua = Lua::new();
+        lua.globals()
+            .set("waveling", build_module(&lua, &program)?)?;
+        lua.load(source)
+            .set_name(format!("@{}", chunk_name))?
+            .exec()?;
+    }
+
+    // The Lua state, and with it every closure holding a reference to the program, is gone now.
+    let program = Rc::try_unwrap(program)
+        .expect("Lua should have dropped all references to the program")
+        .into_inner();
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_building_from_lua() {
+        let mut prog = program_from_lua(
+            r#"
+local x = waveling.input("f32", 2, "in")
+local gain = waveling.property("gain", "f32", { min = 0, max = 2, default = 1, smoothing = 0.05 })
+local offset = waveling.const("f32", 0.5, 1.5)
+local out = waveling.output("f32", 2)
+waveling.connect(waveling.add(waveling.mul(x, gain), offset), out, 0)
+
+local table = waveling.buffer_input("f32", 2, 16)
+local out2 = waveling.output("f32", 2)
+waveling.connect(waveling.read_buffer(table, waveling.const("i64", 3)), out2, 0)
+"#,
+            "test.lua",
+        )
+        .unwrap();
+
+        assert_eq!(prog.inputs, vec![VectorDescriptor::new_f32(2)]);
+        assert_eq!(
+            prog.outputs,
+            vec![VectorDescriptor::new_f32(2), VectorDescriptor::new_f32(2)]
+        );
+        assert_eq!(
+            prog.buffer_inputs,
+            vec![BufferInput {
+                vector: VectorDescriptor::new_f32(2),
+                length: 16
+            }]
+        );
+        assert_eq!(prog.input_names, vec![Some("in".to_string())]);
+        assert_eq!(prog.output_names, vec![None, None]);
+        assert_eq!(
+            prog.properties,
+            vec![PropertyDescriptor::new("gain", PrimitiveType::F32)
+                .with_range(0.0, 2.0)
+                .with_default(1.0)
+                .with_smoothing(0.05)]
+        );
+
+        let mut diags = DiagnosticCollection::new();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        type_inference(&prog, &mut diags).unwrap();
+        assert!(!diags.has_errors(), "{}", diags);
+    }
+
+    #[test]
+    fn test_remaining_ops() {
+        let mut prog = program_from_lua(
+            r#"
+local w = waveling
+local out = w.output("f32", 1)
+local bits = w.output("i64", 1)
+
+-- A one-pole filter over a sine, driven through feedback.
+local phase = w.state("f32", 1, 1)
+local freq = w.cast("f32", w.sr())
+local sine = w.sin_osc(phase, w.div(freq, w.const("f32", 100)), w.const("f32", 0))
+local prev_state = w.state("f32", 1, 1)
+local filtered = w.lerp(w.feedback_read(prev_state), sine, w.const("f32", 0.1))
+w.feedback_write(prev_state, filtered)
+
+-- Gate it with a comparison against a phasor.
+local ramp = w.phasor(w.state("f32", 1, 1), w.const("f32", 2))
+local open = w.logical_and(w.lt(ramp, w.const("f32", 0.5)), w.logical_not(w.const("bool", false)))
+w.connect(w.select(open, filtered, w.const("f32", 0)), out, 0)
+
+-- And some integer work on the clock and seeded noise.
+local seed = w.state("i64", 1, 1)
+w.state_initial(seed, 42)
+local mixed = w.bit_xor(w.shr(w.noise(seed), w.const("i64", 3)), w.bit_not(w.clock()))
+local rounded = w.float_to_int("floor", w.cast("f64", mixed))
+w.connect(w.bit_and(w.shl(rounded, w.const("i64", 1)), w.bit_or(mixed, w.const("i64", 1))), bits, 0)
+"#,
+            "ops.lua",
+        )
+        .unwrap();
+
+        assert_eq!(prog.states[3].initial, Some(Constant::I64(vec![42])));
+        let casts = prog
+            .graph
+            .node_weights()
+            .filter(|n| matches!(n.op, Op::Cast(_)))
+            .count();
+        assert_eq!(casts, 2);
+
+        let mut diags = DiagnosticCollection::new();
+        validate_feedback(&prog, &mut diags).unwrap();
+        insert_start_final_edges(&mut prog, &mut diags).unwrap();
+        type_inference(&prog, &mut diags).unwrap();
+        assert!(!diags.has_errors(), "{}", diags);
+
+        assert!(
+            program_from_lua("waveling.float_to_int(\"up\", waveling.clock())", "bad.lua").is_err()
+        );
+        assert!(program_from_lua("waveling.state_initial(0, 1)", "bad.lua").is_err());
+    }
+
+    #[test]
+    fn test_source_locations() {
+        let prog = program_from_lua(
+            "local x = waveling.input(\"f32\", 1)\nlocal y = waveling.negate(x)\n",
+            "script.lua",
+        )
+        .unwrap();
+
+        let negate = prog
+            .graph
+            .node_indices()
+            .find(|n| prog.graph.node_weight(*n).unwrap().op == Op::Negate)
+            .unwrap();
+        let loc = prog.cloned_source_loc(negate).unwrap();
+        let frame = loc
+            .frames
+            .iter()
+            .find(|f| f.file == "script.lua")
+            .expect("The script should be on the stack");
+        assert_eq!(frame.line, 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(program_from_lua("waveling.input(\"f16\", 1)", "bad.lua").is_err());
+        assert!(program_from_lua("waveling.const(\"f32\")", "bad.lua").is_err());
+
+        // Connecting the same edge twice is rejected by the program, and that should surface here.
+        assert!(program_from_lua(
+            r#"
+local x = waveling.input("f32", 1)
+local out = waveling.output("f32", 1)
+waveling.connect(x, out, 0)
+waveling.connect(x, out, 0)
+"#,
+            "bad.lua"
+        )
+        .is_err());
+    }
+}